@@ -0,0 +1,203 @@
+use serde_json::json;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::{http, truncate, AlertError, Notification};
+use crate::config::{self, AlertMatrixConf, AlertTelegramConf};
+use crate::log;
+use crate::rules::HttpAlert;
+
+/// Retries used when the alert doesn't set any
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Makes each Matrix transaction ID unique within this process
+static TXN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Send the notification to the Telegram chats listed in the contacts
+pub fn telegram(alert: &HttpAlert, contacts: &[String], notification: &Notification) -> Result<(), AlertError> {
+    let config = config::get();
+    let conf = config
+        .alerts
+        .telegram
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.telegram isn't set in the config".to_string()))?;
+
+    send_telegram(conf, alert, contacts, notification)
+}
+
+/// Send the notification to the Matrix rooms listed in the contacts
+pub fn matrix(alert: &HttpAlert, contacts: &[String], notification: &Notification) -> Result<(), AlertError> {
+    let config = config::get();
+    let conf = config
+        .alerts
+        .matrix
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.matrix isn't set in the config".to_string()))?;
+
+    send_matrix(conf, alert, contacts, notification)
+}
+
+/// Title and failures as plain text
+fn message(notification: &Notification) -> String {
+    format!("{}\n\n{}", notification.title(), notification.text())
+}
+
+/// Send to each contact, a contact that fails doesn't stop the others
+fn send_each<F>(contacts: &[String], channel: &str, mut send: F) -> Result<(), AlertError>
+where
+    F: FnMut(&str) -> Result<(), AlertError>,
+{
+    if contacts.is_empty() {
+        return Err(AlertError::Config(format!("{} alerts need contacts", channel)));
+    }
+
+    let mut errors = Vec::new();
+    for contact in contacts {
+        match send(contact) {
+            Ok(_) => log::info(format!("{} alert sent to {}", channel, contact)),
+            Err(e) => errors.push(format!("{}: {}", contact, e)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AlertError::Delivery(errors.join(", ")))
+    }
+}
+
+fn send_telegram(
+    conf: &AlertTelegramConf,
+    alert: &HttpAlert,
+    contacts: &[String],
+    notification: &Notification,
+) -> Result<(), AlertError> {
+    let token = conf
+        .token
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.telegram.token isn't set".to_string()))?;
+
+    let url = format!("{}/bot{}/sendMessage", conf.api_url.trim_end_matches('/'), token.expose());
+    let headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+    let text = truncate(message(notification), 4096);
+
+    send_each(contacts, "Telegram", |chat_id| {
+        let body = json!({
+            "chat_id": chat_id,
+            "text": text,
+            "disable_web_page_preview": true,
+        });
+
+        // The token is part of the URL so keep it out of the error and log messages
+        let retries = alert.retries.unwrap_or(DEFAULT_RETRIES);
+        http::post(&url, &headers, &body.to_string(), retries, &[token.expose()]).map(|_| ())
+    })
+}
+
+fn send_matrix(
+    conf: &AlertMatrixConf,
+    alert: &HttpAlert,
+    contacts: &[String],
+    notification: &Notification,
+) -> Result<(), AlertError> {
+    let token = conf
+        .access_token
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.matrix.access_token isn't set".to_string()))?;
+
+    let headers = vec![
+        ("Content-Type".to_string(), "application/json".to_string()),
+        ("Authorization".to_string(), format!("Bearer {}", token.expose())),
+    ];
+    let body = json!({
+        "msgtype": "m.text",
+        "body": message(notification),
+    })
+    .to_string();
+
+    send_each(contacts, "Matrix", |room_id| {
+        // Retries reuse the transaction ID so the homeserver doesn't post the message twice
+        let txn_id = format!(
+            "eekwatch-{}-{}",
+            notification.timestamp.timestamp_millis(),
+            TXN_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/{}",
+            conf.homeserver.trim_end_matches('/'),
+            http::encode_path(room_id),
+            txn_id
+        );
+
+        http::send("PUT", &url, &headers, &body, alert.retries.unwrap_or(DEFAULT_RETRIES), &[]).map(|_| ())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::http::test_server;
+    use crate::alerts::test_notification;
+    use crate::rules::Severity;
+    use crate::secret::Secret;
+
+    fn notification() -> Notification {
+        test_notification(&[("/", Severity::Critical)])
+    }
+
+    fn alert() -> HttpAlert {
+        HttpAlert { retries: Some(0) }
+    }
+
+    fn contacts(contacts: &[&str]) -> Vec<String> {
+        contacts.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_telegram() {
+        let (url, server) = test_server::serve(&[200, 400]);
+        let conf = AlertTelegramConf {
+            token: Some(Secret::new("123:abc".to_string())),
+            token_file: None,
+            api_url: url,
+        };
+
+        // The first chat gets the alert even though the second fails
+        let result = send_telegram(&conf, &alert(), &contacts(&["-100200", "@missing"]), &notification());
+        let Err(AlertError::Delivery(e)) = result else {
+            panic!("expected a delivery error");
+        };
+        assert!(e.contains("@missing"));
+        assert!(!e.contains("123:abc"));
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].path, "/bot123:abc/sendMessage");
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["chat_id"], "-100200");
+        assert!(body["text"].as_str().unwrap().contains("Disk / is low"));
+    }
+
+    #[test]
+    fn test_matrix() {
+        let (url, server) = test_server::serve(&[200]);
+        let conf = AlertMatrixConf {
+            homeserver: url,
+            access_token: Some(Secret::new("syt_token".to_string())),
+            access_token_file: None,
+        };
+
+        send_matrix(&conf, &alert(), &contacts(&["!room:example.com"]), &notification()).unwrap();
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.method, "PUT");
+        assert!(request
+            .path
+            .starts_with("/_matrix/client/v3/rooms/%21room%3Aexample.com/send/m.room.message/eekwatch-"));
+        assert_eq!(request.header("authorization"), Some("Bearer syt_token"));
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["msgtype"], "m.text");
+        assert!(body["body"].as_str().unwrap().contains("Failed Check: disks"));
+
+        let result = send_matrix(&conf, &alert(), &[], &notification());
+        assert!(matches!(result, Err(AlertError::Config(_))));
+    }
+}
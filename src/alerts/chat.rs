@@ -0,0 +1,270 @@
+use serde_json::{json, Value};
+
+use super::{http, truncate, AlertError, Notification};
+use crate::log;
+use crate::rules::{ChatAlert, Severity};
+
+/// Retries used when the alert doesn't set any
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Send the notification to a Slack, Discord or Teams incoming webhook
+pub fn alert(chat_type: &str, chat: &ChatAlert, notification: &Notification) -> Result<(), AlertError> {
+    let message = match chat_type {
+        "slack" => slack_message(notification),
+        "discord" => discord_message(notification),
+        "teams" => teams_message(notification),
+        _ => return Err(AlertError::Config(format!("unknown chat type '{}'", chat_type))),
+    };
+
+    let mut headers: Vec<(String, String)> = chat.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    headers.push(("Content-Type".to_string(), "application/json".to_string()));
+
+    http::post(&chat.url, &headers, &message.to_string(), chat.retries.unwrap_or(DEFAULT_RETRIES), &[])?;
+    log::info(format!("{} alert sent for rule '{}'", chat_type, notification.rule));
+
+    Ok(())
+}
+
+/// Colour of Discord embeds holding diagnostics
+const DIAGNOSTIC_COLOUR: u32 = 0x95a5a6;
+
+/// Colour of the message as an RGB value
+fn colour(severity: Severity) -> u32 {
+    match severity {
+        Severity::Info => 0x2e86de,
+        Severity::Warning => 0xf39c12,
+        Severity::Critical => 0xe74c3c,
+    }
+}
+
+/// Slack blocks inside an attachment so the message gets a coloured bar
+fn slack_message(notification: &Notification) -> Value {
+    let mut blocks = vec![json!({
+        "type": "header",
+        "text": {"type": "plain_text", "text": truncate(notification.title(), 150)},
+    })];
+
+    for failure in &notification.failures {
+        let text = format!("*{}* ({})\n{}", failure.entry, failure.severity, failure.details("*"));
+        blocks.push(json!({
+            "type": "section",
+            "text": {"type": "mrkdwn", "text": truncate(text, 3000)},
+        }));
+    }
+
+    for diagnostic in &notification.diagnostics {
+        let text = format!("*{}*\n```{}```", diagnostic.title, diagnostic.lines.join("\n"));
+        blocks.push(json!({
+            "type": "section",
+            "text": {"type": "mrkdwn", "text": truncate(text, 3000)},
+        }));
+    }
+
+    blocks.push(json!({
+        "type": "context",
+        "elements": [{"type": "mrkdwn", "text": notification.timestamp.to_rfc2822()}],
+    }));
+
+    json!({
+        "text": notification.title(),
+        "attachments": [{
+            "color": format!("#{:06x}", colour(notification.severity)),
+            "blocks": blocks,
+        }],
+    })
+}
+
+/// One Discord embed per failure, coloured by its severity
+fn discord_message(notification: &Notification) -> Value {
+    // Discord allows up to 10 embeds in a message
+    let mut embeds: Vec<Value> = notification
+        .failures
+        .iter()
+        .take(10)
+        .map(|failure| {
+            json!({
+                "title": truncate(format!("{} ({})", failure.entry, failure.severity), 256),
+                "description": truncate(failure.details("**"), 4096),
+                "color": colour(failure.severity),
+                "timestamp": notification.timestamp.to_rfc3339(),
+            })
+        })
+        .collect();
+
+    if notification.failures.len() > 10 {
+        if let Some(last) = embeds.last_mut() {
+            last["footer"] = json!({"text": format!("{} more failures not shown", notification.failures.len() - 10)});
+        }
+    }
+
+    // Diagnostics use up whatever embeds are left
+    let room = 10 - embeds.len();
+    for diagnostic in notification.diagnostics.iter().take(room) {
+        embeds.push(json!({
+            "title": truncate(diagnostic.title.clone(), 256),
+            "description": truncate(format!("```\n{}\n```", diagnostic.lines.join("\n")), 4096),
+            "color": DIAGNOSTIC_COLOUR,
+        }));
+    }
+
+    json!({
+        "content": truncate(notification.title(), 2000),
+        "embeds": embeds,
+    })
+}
+
+/// A Teams Adaptive Card with a container per failure
+fn teams_message(notification: &Notification) -> Value {
+    let style = |severity: Severity| match severity {
+        Severity::Info => "accent",
+        Severity::Warning => "warning",
+        Severity::Critical => "attention",
+    };
+
+    let mut body = vec![
+        json!({
+            "type": "TextBlock",
+            "text": notification.title(),
+            "size": "Large",
+            "weight": "Bolder",
+            "color": style(notification.severity),
+            "wrap": true,
+        }),
+        json!({
+            "type": "TextBlock",
+            "text": notification.timestamp.to_rfc2822(),
+            "isSubtle": true,
+            "spacing": "None",
+        }),
+    ];
+
+    for failure in &notification.failures {
+        body.push(json!({
+            "type": "Container",
+            "style": style(failure.severity),
+            "bleed": true,
+            "items": [
+                {
+                    "type": "TextBlock",
+                    "text": format!("{} ({})", failure.entry, failure.severity),
+                    "weight": "Bolder",
+                    "wrap": true,
+                },
+                {
+                    // Teams needs a blank line between lines of text
+                    "type": "TextBlock",
+                    "text": failure.details("**").replace('\n', "\n\n"),
+                    "wrap": true,
+                },
+            ],
+        }));
+    }
+
+    for diagnostic in &notification.diagnostics {
+        body.push(json!({
+            "type": "Container",
+            "items": [
+                {
+                    "type": "TextBlock",
+                    "text": diagnostic.title,
+                    "weight": "Bolder",
+                    "wrap": true,
+                },
+                {
+                    "type": "TextBlock",
+                    "text": diagnostic.lines.join("\n\n"),
+                    "fontType": "Monospace",
+                    "wrap": true,
+                },
+            ],
+        }));
+    }
+
+    json!({
+        "type": "message",
+        "attachments": [{
+            "contentType": "application/vnd.microsoft.card.adaptive",
+            "content": {
+                "$schema": "http://adaptivecards.io/schemas/adaptive-card.json",
+                "type": "AdaptiveCard",
+                "version": "1.4",
+                "msteams": {"width": "Full"},
+                "body": body,
+            },
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::http::test_server;
+    use crate::alerts::{test_notification, Diagnostic};
+
+    fn notification() -> Notification {
+        test_notification(&[("/", Severity::Critical), ("/data", Severity::Warning)])
+    }
+
+    #[test]
+    fn test_slack_message() {
+        let message = slack_message(&notification());
+        let attachment = &message["attachments"][0];
+        assert_eq!(attachment["color"], "#e74c3c");
+
+        // Header, a section per failure and the timestamp
+        let blocks = attachment["blocks"].as_array().unwrap();
+        assert_eq!(blocks.len(), 4);
+        assert!(blocks[1]["text"]["text"].as_str().unwrap().starts_with("*/* (critical)"));
+        assert!(blocks[2]["text"]["text"].as_str().unwrap().contains("*Threshold:* 20% free"));
+    }
+
+    #[test]
+    fn test_discord_message() {
+        let message = discord_message(&notification());
+        let embeds = message["embeds"].as_array().unwrap();
+        assert_eq!(embeds.len(), 2);
+        assert_eq!(embeds[0]["color"], 0xe74c3c);
+        assert_eq!(embeds[1]["color"], 0xf39c12);
+        assert!(embeds[1]["description"].as_str().unwrap().contains("Disk /data is low"));
+    }
+
+    #[test]
+    fn test_teams_message() {
+        let message = teams_message(&notification());
+        let body = message["attachments"][0]["content"]["body"].as_array().unwrap();
+        assert_eq!(body.len(), 4);
+        assert_eq!(body[0]["color"], "attention");
+        assert_eq!(body[3]["style"], "warning");
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut notification = notification();
+        notification.diagnostics.push(Diagnostic {
+            title: "Load average".to_string(),
+            lines: vec!["0.50 0.40 0.30".to_string()],
+        });
+
+        let blocks = slack_message(&notification)["attachments"][0]["blocks"].clone();
+        assert_eq!(blocks[3]["text"]["text"], "*Load average*\n```0.50 0.40 0.30```");
+
+        let embeds = discord_message(&notification)["embeds"].clone();
+        assert_eq!(embeds[2]["color"], DIAGNOSTIC_COLOUR);
+
+        let body = teams_message(&notification)["attachments"][0]["content"]["body"].clone();
+        assert_eq!(body[4]["items"][1]["fontType"], "Monospace");
+    }
+
+    #[test]
+    fn test_alert() {
+        let (url, server) = test_server::serve(&[200]);
+        let chat: ChatAlert = serde_json::from_value(json!({"url": url, "retries": 0})).unwrap();
+
+        super::alert("discord", &chat, &notification()).unwrap();
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        let body: Value = serde_json::from_str(&request.body).unwrap();
+        assert!(body["content"].as_str().unwrap().ends_with("Failed Check: disks"));
+    }
+}
@@ -0,0 +1,280 @@
+use chrono::{DateTime, Local, Utc};
+use std::time::Duration;
+
+use super::{quiet, Notification};
+use crate::config::{self, ContactsConf, OnCallConf};
+use crate::rules::Alert;
+use crate::{duration, log};
+
+/// Prefix of a contact that refers to a contact group
+const GROUP_PREFIX: &str = "group:";
+
+/// Prefix of a contact that refers to whoever is on call in a rotation
+const ONCALL_PREFIX: &str = "oncall:";
+
+/// Deepest nesting of groups and rotations, stops groups that include each other looping forever
+const MAX_DEPTH: usize = 8;
+
+/// Who an alert goes to
+#[derive(Debug, Default, PartialEq)]
+pub struct Recipients {
+    pub to: Vec<String>,
+    /// Contacts left out because they are in quiet hours
+    pub quiet: Vec<String>,
+}
+
+impl Recipients {
+    /// True when the alert had contacts but all of them are in quiet hours
+    pub fn all_quiet(&self) -> bool {
+        self.to.is_empty() && !self.quiet.is_empty()
+    }
+}
+
+/// Contacts to notify for an alert, with escalation applied, groups and rotations expanded and
+/// anyone in quiet hours left out
+pub fn for_alert(name: &str, alert: &Alert, notification: &Notification) -> Result<Recipients, String> {
+    let config = config::get();
+    let alert_type = alert.alert_type();
+    let all = resolve(&config.contacts, alert, notification, Utc::now())?;
+    let quiet = quiet::quiet_contacts(&config.quiet_hours, &config.contacts, name, alert_type, Local::now())?;
+
+    let (quiet, to) = all.into_iter().partition(|c| quiet.contains(c));
+    Ok(Recipients { to, quiet })
+}
+
+fn resolve(
+    conf: &ContactsConf,
+    alert: &Alert,
+    notification: &Notification,
+    now: DateTime<Utc>,
+) -> Result<Vec<String>, String> {
+    // The failure that has been alerting longest decides the escalation level
+    let alerting_for = notification
+        .failures
+        .iter()
+        .filter_map(|f| f.alerting_since)
+        .min()
+        .and_then(|since| (now - since).to_std().ok())
+        .unwrap_or(Duration::ZERO);
+
+    let mut contacts = alert.contacts.clone();
+    for (i, level) in alert.escalation.iter().enumerate() {
+        let after = duration::parse(&level.after).ok_or(format!("invalid escalation after: {}", level.after))?;
+        if alerting_for >= after {
+            log::debug(format!(
+                "Rule '{}' has been alerting for {:?}, notifying escalation level {}",
+                notification.rule,
+                alerting_for,
+                i + 1
+            ));
+            contacts.extend(level.contacts.iter().cloned());
+        }
+    }
+
+    expand(conf, &contacts, now)
+}
+
+/// Replace group and on-call references with the contacts they stand for, without duplicates
+pub fn expand(conf: &ContactsConf, contacts: &[String], now: DateTime<Utc>) -> Result<Vec<String>, String> {
+    let mut out = Vec::new();
+    for contact in contacts {
+        expand_one(conf, contact, now, 0, &mut out)?;
+    }
+    Ok(out)
+}
+
+fn expand_one(
+    conf: &ContactsConf,
+    contact: &str,
+    now: DateTime<Utc>,
+    depth: usize,
+    out: &mut Vec<String>,
+) -> Result<(), String> {
+    if depth > MAX_DEPTH {
+        return Err(format!("contact '{}' is nested too deep, check for groups that include each other", contact));
+    }
+
+    if let Some(name) = contact.strip_prefix(GROUP_PREFIX) {
+        let members = conf
+            .groups
+            .get(name)
+            .ok_or(format!("unknown contact group '{}'", name))?;
+        for member in members {
+            expand_one(conf, member, now, depth + 1, out)?;
+        }
+    } else if let Some(name) = contact.strip_prefix(ONCALL_PREFIX) {
+        let rotation = conf
+            .oncall
+            .get(name)
+            .ok_or(format!("unknown on-call rotation '{}'", name))?;
+        let member = on_call(rotation, now).map_err(|e| format!("on-call rotation '{}': {}", name, e))?;
+        expand_one(conf, member, now, depth + 1, out)?;
+    } else if !out.iter().any(|c| c == contact) {
+        out.push(contact.to_string());
+    }
+
+    Ok(())
+}
+
+/// The member on call at a time, before the rotation starts the first member is on call
+pub fn on_call(rotation: &OnCallConf, now: DateTime<Utc>) -> Result<&str, String> {
+    let shift = duration::parse(&rotation.shift)
+        .filter(|s| s.as_secs() > 0)
+        .ok_or(format!("invalid shift: {}", rotation.shift))?;
+    if rotation.members.is_empty() {
+        return Err("no members".to_string());
+    }
+
+    let elapsed = now.signed_duration_since(rotation.start).num_seconds().max(0) as u64;
+    let shifts = elapsed / shift.as_secs();
+
+    Ok(&rotation.members[(shifts % rotation.members.len() as u64) as usize])
+}
+
+/// Check the contact groups and rotations in the config can all be expanded
+pub fn validate_conf(conf: &ContactsConf) -> Result<(), String> {
+    let now = Utc::now();
+
+    for (name, members) in &conf.groups {
+        expand(conf, members, now).map_err(|e| format!("Invalid contact group '{}': {}", name, e))?;
+    }
+    for (name, rotation) in &conf.oncall {
+        // Every member has to be valid, not just whoever is on call now
+        on_call(rotation, now).map_err(|e| format!("Invalid on-call rotation '{}': {}", name, e))?;
+        expand(conf, &rotation.members, now).map_err(|e| format!("Invalid on-call rotation '{}': {}", name, e))?;
+    }
+
+    Ok(())
+}
+
+/// Check an alert's escalation levels and that its contacts refer to known groups and rotations
+pub fn validate(alert: &Alert, conf: &ContactsConf) -> Result<(), String> {
+    for level in &alert.escalation {
+        duration::parse(&level.after).ok_or(format!("invalid escalation after: {}", level.after))?;
+    }
+
+    let all = alert
+        .contacts
+        .iter()
+        .chain(alert.escalation.iter().flat_map(|l| l.contacts.iter()))
+        .cloned()
+        .collect::<Vec<_>>();
+    expand(conf, &all, Utc::now())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::Failure;
+    use crate::rules::Severity;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn conf() -> ContactsConf {
+        serde_json::from_value(json!({
+            "groups": {
+                "ops": ["ops@example.com", "oncall:primary"],
+                "leads": ["lead@example.com", "group:ops"],
+                "loop": ["group:loop"],
+            },
+            "oncall": {
+                "primary": {
+                    "members": ["ann@example.com", "bob@example.com", "cat@example.com"],
+                    "start": "2024-06-03T09:00:00+00:00",
+                },
+            },
+        }))
+        .unwrap()
+    }
+
+    fn at(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 6, day, hour, 0, 0).unwrap()
+    }
+
+    fn contacts(list: &[&str]) -> Vec<String> {
+        list.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn test_on_call() {
+        let conf = conf();
+        let rotation = &conf.oncall["primary"];
+
+        // Before the start the first member is on call
+        assert_eq!(on_call(rotation, at(1, 0)), Ok("ann@example.com"));
+        assert_eq!(on_call(rotation, at(10, 8)), Ok("ann@example.com"));
+        assert_eq!(on_call(rotation, at(10, 9)), Ok("bob@example.com"));
+        assert_eq!(on_call(rotation, at(17, 9)), Ok("cat@example.com"));
+        // And back round to the start
+        assert_eq!(on_call(rotation, at(24, 9)), Ok("ann@example.com"));
+    }
+
+    #[test]
+    fn test_expand() {
+        let conf = conf();
+
+        let expanded = expand(&conf, &contacts(&["group:leads", "ops@example.com"]), at(10, 12)).unwrap();
+        assert_eq!(expanded, contacts(&["lead@example.com", "ops@example.com", "bob@example.com"]));
+
+        assert!(expand(&conf, &contacts(&["group:missing"]), at(10, 12)).is_err());
+        assert!(expand(&conf, &contacts(&["group:loop"]), at(10, 12)).unwrap_err().contains("nested too deep"));
+    }
+
+    #[test]
+    fn test_escalation() {
+        let conf = conf();
+        let alert: Alert = serde_json::from_value(json!({
+            "alert_type": "email",
+            "contacts": ["oncall:primary"],
+            "escalation": [
+                {"after": "30m", "contacts": ["group:ops"]},
+                {"after": "2h", "contacts": ["lead@example.com"]},
+            ],
+        }))
+        .unwrap();
+
+        let start = at(10, 12);
+        let mut notification = Notification::new(
+            "disks",
+            vec![Failure {
+                entry: "/".to_string(),
+                severity: Severity::Critical,
+                value: None,
+                threshold: None,
+                failing_since: Some(start),
+                alerting_since: Some(start),
+                msg: Vec::new(),
+            }],
+        );
+
+        let now = start + chrono::Duration::minutes(10);
+        assert_eq!(resolve(&conf, &alert, &notification, now).unwrap(), contacts(&["bob@example.com"]));
+
+        let now = start + chrono::Duration::minutes(45);
+        assert_eq!(
+            resolve(&conf, &alert, &notification, now).unwrap(),
+            contacts(&["bob@example.com", "ops@example.com"])
+        );
+
+        let now = start + chrono::Duration::hours(3);
+        assert_eq!(resolve(&conf, &alert, &notification, now).unwrap().len(), 3);
+
+        // Test alerts aren't alerting yet so only get the first level
+        notification.failures[0].alerting_since = None;
+        assert_eq!(resolve(&conf, &alert, &notification, now).unwrap(), contacts(&["bob@example.com"]));
+    }
+
+    #[test]
+    fn test_validate_conf() {
+        assert!(validate_conf(&ContactsConf::default()).is_ok());
+
+        let mut conf = conf();
+        conf.groups.remove("loop");
+        assert!(validate_conf(&conf).is_ok());
+
+        conf.oncall.get_mut("primary").unwrap().shift = "never".to_string();
+        assert!(validate_conf(&conf).is_err());
+    }
+}
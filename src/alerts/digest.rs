@@ -0,0 +1,200 @@
+use chrono::{DateTime, Utc};
+use std::sync::{Mutex, PoisonError};
+
+use super::{notify_channel, Diagnostic, Notification};
+use crate::rules::Alert;
+use crate::{config, duration, log};
+
+/// Alerts waiting to be sent in the next digest
+struct Queue {
+    /// When the oldest alert was added
+    since: Option<DateTime<Utc>>,
+    pending: Vec<(String, Alert, Notification)>,
+}
+
+static QUEUE: Mutex<Queue> = Mutex::new(Queue {
+    since: None,
+    pending: Vec::new(),
+});
+
+/// True when alerts are collected into digests
+pub fn enabled() -> bool {
+    config::get().alerts.digest.is_some()
+}
+
+/// Hold an alert back for the next digest
+pub fn add(name: &str, alert: &Alert, notification: &Notification) {
+    let mut queue = QUEUE.lock().unwrap_or_else(PoisonError::into_inner);
+    queue.since.get_or_insert(Utc::now());
+    replace_older(&mut queue.pending, name, alert, notification.clone());
+}
+
+/// Queue a notification, dropping failures queued earlier for the same channel, rule and entry
+///
+/// The daemon alerts on every check, so only the latest failure of each entry is kept.
+fn replace_older(
+    pending: &mut Vec<(String, Alert, Notification)>,
+    name: &str,
+    alert: &Alert,
+    notification: Notification,
+) {
+    for (_, _, older) in pending
+        .iter_mut()
+        .filter(|(n, a, o)| n == name && a == alert && o.rule == notification.rule)
+    {
+        older
+            .failures
+            .retain(|f| !notification.failures.iter().any(|new| new.entry == f.entry));
+        older.severity = older.failures.iter().map(|f| f.severity).max().unwrap_or_default();
+    }
+    pending.retain(|(_, _, n)| !n.failures.is_empty());
+
+    pending.push((name.to_string(), alert.clone(), notification));
+}
+
+/// True when the oldest alert waiting has been held for the digest window
+///
+/// Alerts still waiting after digests are turned off by a reload are due straight away.
+pub fn due(now: DateTime<Utc>) -> bool {
+    let window = config::get().alerts.digest.as_ref().map(|digest| {
+        duration::parse(&digest.window)
+            .and_then(|w| chrono::Duration::from_std(w).ok())
+            .unwrap_or_default()
+    });
+
+    let queue = QUEUE.lock().unwrap_or_else(PoisonError::into_inner);
+    queue
+        .since
+        .is_some_and(|since| window.map_or(true, |window| now - since >= window))
+}
+
+/// Send everything waiting, one alert per channel
+pub fn flush() {
+    let pending = {
+        let mut queue = QUEUE.lock().unwrap_or_else(PoisonError::into_inner);
+        queue.since = None;
+        std::mem::take(&mut queue.pending)
+    };
+
+    for (name, alert, notifications) in group(pending) {
+        let notification = combine(notifications);
+        log::debug(format!(
+            "Sending digest of {} failure(s) through alert '{}'",
+            notification.failures.len(),
+            name
+        ));
+        notify_channel(&name, &alert, &notification);
+    }
+}
+
+/// Group the alerts going through the same channel with the same settings, keeping their order
+fn group(pending: Vec<(String, Alert, Notification)>) -> Vec<(String, Alert, Vec<Notification>)> {
+    let mut groups: Vec<(String, Alert, Vec<Notification>)> = Vec::new();
+
+    for (name, alert, notification) in pending {
+        match groups.iter_mut().find(|(n, a, _)| *n == name && *a == alert) {
+            Some((_, _, notifications)) => notifications.push(notification),
+            None => groups.push((name, alert, vec![notification])),
+        }
+    }
+
+    groups
+}
+
+/// Merge the notifications into one, entries and diagnostics are labelled with their rule
+///
+/// A single notification is sent as it is.
+fn combine(mut notifications: Vec<Notification>) -> Notification {
+    if notifications.len() == 1 {
+        return notifications.remove(0);
+    }
+
+    let mut rules: Vec<String> = Vec::new();
+    let mut failures = Vec::new();
+    let mut diagnostics = Vec::new();
+
+    for notification in notifications {
+        let rule = notification.rule;
+        for mut failure in notification.failures {
+            failure.entry = format!("{}: {}", rule, failure.entry);
+            failures.push(failure);
+        }
+        diagnostics.extend(notification.diagnostics.into_iter().map(|d| Diagnostic {
+            title: format!("{}: {}", rule, d.title),
+            lines: d.lines,
+        }));
+        if !rules.contains(&rule) {
+            rules.push(rule);
+        }
+    }
+
+    let mut digest = Notification::new(&rules.join(", "), failures);
+    digest.diagnostics = diagnostics;
+    digest.digest = true;
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::test_notification;
+    use crate::rules::Severity;
+    use serde_json::json;
+
+    fn notification(rule: &str, severities: &[Severity]) -> Notification {
+        let entries: Vec<(&str, Severity)> = severities.iter().map(|s| ("/", *s)).collect();
+        let mut notification = test_notification(&entries);
+        notification.rule = rule.to_string();
+        notification
+    }
+
+    #[test]
+    fn test_group() {
+        let ops: Alert = serde_json::from_value(json!({"alert_type": "email", "contacts": ["ops@example.com"]})).unwrap();
+        let dev: Alert = serde_json::from_value(json!({"alert_type": "email", "contacts": ["dev@example.com"]})).unwrap();
+        let pending = vec![
+            ("email".to_string(), ops.clone(), notification("disks", &[Severity::Warning])),
+            ("email".to_string(), dev, notification("logs", &[Severity::Warning])),
+            ("email".to_string(), ops, notification("backups", &[Severity::Critical])),
+        ];
+
+        let groups = group(pending);
+        assert_eq!(groups.len(), 2);
+        let rules: Vec<&str> = groups[0].2.iter().map(|n| n.rule.as_str()).collect();
+        assert_eq!(rules, vec!["disks", "backups"]);
+    }
+
+    #[test]
+    fn test_latest_failure_kept() {
+        let alert: Alert = serde_json::from_value(json!({"alert_type": "email", "contacts": ["ops@example.com"]})).unwrap();
+        let mut pending = Vec::new();
+
+        replace_older(&mut pending, "email", &alert, notification("disks", &[Severity::Warning]));
+        replace_older(&mut pending, "email", &alert, notification("logs", &[Severity::Warning]));
+        replace_older(&mut pending, "email", &alert, notification("disks", &[Severity::Critical]));
+
+        let digest = combine(group(pending).remove(0).2);
+        assert_eq!(digest.failures.len(), 2);
+        assert_eq!(digest.severity_counts(), "1 critical, 1 warning");
+        assert_eq!(digest.rule, "logs, disks");
+    }
+
+    #[test]
+    fn test_combine() {
+        let digest = combine(vec![
+            notification("disks", &[Severity::Warning, Severity::Info]),
+            notification("backups", &[Severity::Critical, Severity::Warning]),
+        ]);
+
+        assert!(digest.digest);
+        assert_eq!(digest.rule, "disks, backups");
+        assert_eq!(digest.severity, Severity::Critical);
+        assert_eq!(digest.failures[2].entry, "backups: /");
+        assert_eq!(digest.severity_counts(), "1 critical, 2 warning, 1 info");
+        assert!(digest.title().ends_with("- 4 Failed Checks (1 critical, 2 warning, 1 info)"));
+
+        let single = combine(vec![notification("disks", &[Severity::Warning])]);
+        assert!(!single.digest);
+        assert!(single.title().ends_with("Failed Check: disks"));
+    }
+}
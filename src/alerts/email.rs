@@ -1,41 +1,314 @@
 use lettre::message::header::ContentType;
+use lettre::message::{Attachment, Mailbox, MultiPart};
 use lettre::transport::smtp::authentication::Credentials;
-use lettre::{Message, SmtpTransport, Transport};
-use sysinfo::System;
-
-use crate::config::CONFIG;
-
-pub fn alert(check: &String, msg: String, contacts: &Vec<String>) {
-    let config = CONFIG.get().unwrap();
-
-    let to = contacts.join(", ");
-    let from = &config.alerts.email.from_address;
-    let hostname = System::host_name().unwrap_or("UNKNOWN".to_string());
-    let subject = format!("{} - Failed Check: {}", hostname, check);
-    let timestamp = chrono::Utc::now().to_rfc2822();
-
-    let email = Message::builder()
-        .from(from.parse().unwrap())
-        .to(to.parse().unwrap())
-        .subject(subject)
-        .header(ContentType::TEXT_HTML)
-        .body(format!("<h3>{}</h3><b>{}</b>", timestamp, msg))
-        .unwrap();
+use lettre::transport::smtp::client::{Tls, TlsParameters};
+use lettre::{Message, SendmailTransport, SmtpTransport, Transport};
+
+use super::{AlertError, Notification};
+use crate::config::{self, AlertEmailConf, EmailTransport, TlsMode};
+use crate::template::{Escape, Template};
+use crate::{duration, log};
 
-    let smtp_user = config.alerts.email.user.clone();
-    let smtp_pass = config.alerts.email.password.clone();
+const DEFAULT_SUBJECT: &str = "{{title}}";
+const DEFAULT_TEXT: &str = include_str!("templates/email.txt");
+const DEFAULT_HTML: &str = include_str!("templates/email.html");
 
-    let creds = Credentials::new(smtp_user, smtp_pass);
+/// Transport used to deliver the email
+enum Mailer {
+    Smtp(SmtpTransport),
+    Sendmail(SendmailTransport),
+}
 
-    // Open a remote connection to gmail
-    let mailer = SmtpTransport::relay(&config.alerts.email.smtp)
-        .unwrap()
-        .credentials(creds)
-        .build();
+pub fn alert(notification: &Notification, contacts: &[String]) -> Result<(), AlertError> {
+    let config = config::get();
 
     // Send the email
-    match mailer.send(&email) {
-        Ok(_) => println!("Email sent successfully!"),
-        Err(e) => panic!("Could not send email: {e:?}"),
+    send(&config.alerts.email, notification, contacts)?;
+    log::info(format!("Email alert sent to {}", contacts.join(", ")));
+
+    Ok(())
+}
+
+fn send(conf: &AlertEmailConf, notification: &Notification, contacts: &[String]) -> Result<(), AlertError> {
+    let from: Mailbox = conf.from_address.parse().map_err(|e| {
+        AlertError::Config(format!("invalid from_address '{}': {}", conf.from_address, e))
+    })?;
+
+    let ctx = notification.context();
+    let templates = &conf.templates;
+    let render = |custom: &Option<String>, default: &str, escape: Escape| {
+        Template::parse(custom.as_deref().unwrap_or(default))
+            .map(|t| t.render(&ctx, escape))
+            .map_err(|e| AlertError::Config(format!("invalid email template: {}", e)))
+    };
+
+    // Subjects can't have new lines in them
+    let subject = render(&templates.subject, DEFAULT_SUBJECT, Escape::None)?.replace(['\r', '\n'], " ");
+    let text = render(&templates.text, DEFAULT_TEXT, Escape::None)?;
+    let html = render(&templates.html, DEFAULT_HTML, Escape::Html)?;
+
+    let mut builder = Message::builder().from(from).subject(subject);
+
+    // A bad address is skipped so the other contacts still get the alert
+    let mut recipients = 0;
+    for contact in contacts {
+        match contact.parse::<Mailbox>() {
+            Ok(mailbox) => {
+                builder = builder.to(mailbox);
+                recipients += 1;
+            }
+            Err(e) => log::error(format!("Skipping invalid email contact '{}': {}", contact, e)),
+        }
+    }
+
+    if recipients == 0 {
+        return Err(AlertError::Message("no valid email contacts".to_string()));
+    }
+
+    let mut body = MultiPart::alternative_plain_html(text, html);
+    // The diagnostics are attached too so they can be kept or forwarded on their own
+    if !notification.diagnostics.is_empty() {
+        let diagnostics: Vec<String> = notification.diagnostics.iter().map(|d| d.text()).collect();
+        let attachment =
+            Attachment::new("diagnostics.txt".to_string()).body(diagnostics.join("\n\n"), ContentType::TEXT_PLAIN);
+        body = MultiPart::mixed().multipart(body).singlepart(attachment);
+    }
+
+    let email = builder.multipart(body).map_err(|e| AlertError::Message(e.to_string()))?;
+
+    let mailer = build_mailer(conf).map_err(AlertError::Config)?;
+    let sent = match mailer {
+        Mailer::Smtp(mailer) => mailer.send(&email).map(|_| ()).map_err(|e| e.to_string()),
+        Mailer::Sendmail(mailer) => mailer.send(&email).map_err(|e| e.to_string()),
+    };
+
+    sent.map_err(AlertError::Delivery)
+}
+
+/// Build the email transport from the config
+fn build_mailer(conf: &AlertEmailConf) -> Result<Mailer, String> {
+    if conf.transport == EmailTransport::Sendmail {
+        let mailer = match &conf.sendmail_command {
+            Some(cmd) => SendmailTransport::new_with_command(cmd),
+            None => SendmailTransport::new(),
+        };
+        return Ok(Mailer::Sendmail(mailer));
+    }
+
+    if conf.smtp.is_empty() {
+        return Err("No SMTP server set in alerts.email.smtp".to_string());
+    }
+
+    let builder = match conf.tls {
+        TlsMode::None => SmtpTransport::builder_dangerous(&conf.smtp).port(25),
+        TlsMode::Starttls => {
+            let params = TlsParameters::new(conf.smtp.clone()).map_err(|e| e.to_string())?;
+            SmtpTransport::builder_dangerous(&conf.smtp)
+                .port(587)
+                .tls(Tls::Required(params))
+        }
+        TlsMode::Implicit => {
+            let params = TlsParameters::new(conf.smtp.clone()).map_err(|e| e.to_string())?;
+            SmtpTransport::builder_dangerous(&conf.smtp)
+                .port(465)
+                .tls(Tls::Wrapper(params))
+        }
+    };
+
+    let mut builder = match conf.port {
+        Some(port) => builder.port(port),
+        None => builder,
+    };
+
+    // Only authenticate when a user is set, local relays often don't need it
+    if let Some(user) = conf.user.as_ref().filter(|u| !u.is_empty()) {
+        let password = conf
+            .password
+            .as_ref()
+            .map(|p| p.expose().to_string())
+            .unwrap_or_default();
+        builder = builder.credentials(Credentials::new(user.clone(), password));
+    }
+
+    if let Some(timeout) = &conf.timeout {
+        let timeout = duration::parse(timeout)
+            .ok_or(format!("Invalid alerts.email.timeout: {}", timeout))?;
+        builder = builder.timeout(Some(timeout));
+    }
+
+    Ok(Mailer::Smtp(builder.build()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::test_notification;
+    use crate::rules::Severity;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    fn smtp_conf(port: u16) -> AlertEmailConf {
+        let mut conf: AlertEmailConf = serde_json::from_str(
+            r#"{"smtp": "127.0.0.1", "tls": "none", "from_address": "eekwatch@example.com"}"#,
+        )
+        .unwrap();
+        conf.port = Some(port);
+        conf.timeout = Some("5s".to_string());
+        conf
+    }
+
+    /// Accept a single SMTP session and return everything the client sent
+    fn fake_smtp_server() -> (u16, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut received = String::new();
+            let mut in_data = false;
+
+            writer.write_all(b"220 localhost ESMTP\r\n").unwrap();
+
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                received.push_str(&line);
+
+                if in_data {
+                    if line == ".\r\n" {
+                        in_data = false;
+                        writer.write_all(b"250 OK\r\n").unwrap();
+                    }
+                    continue;
+                }
+
+                let cmd = line.to_uppercase();
+                let reply: &[u8] = if cmd.starts_with("EHLO") {
+                    b"250 localhost\r\n"
+                } else if cmd.starts_with("DATA") {
+                    in_data = true;
+                    b"354 Go ahead\r\n"
+                } else if cmd.starts_with("QUIT") {
+                    writer.write_all(b"221 Bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 OK\r\n"
+                };
+                writer.write_all(reply).unwrap();
+            }
+
+            received
+        });
+
+        (port, handle)
+    }
+
+    fn notification() -> Notification {
+        test_notification(&[("/", Severity::Critical)])
+    }
+
+    #[test]
+    fn test_send_without_auth() {
+        let (port, server) = fake_smtp_server();
+        let conf = smtp_conf(port);
+
+        let mut notification = notification();
+        notification.failures[0].msg = vec!["Disk <full>".to_string()];
+        send(&conf, &notification, &["ops@example.com".to_string()]).unwrap();
+
+        let received = server.join().unwrap();
+        assert!(received.contains("MAIL FROM:<eekwatch@example.com>"));
+        assert!(received.contains("RCPT TO:<ops@example.com>"));
+        assert!(received.contains("Subject: [critical]"));
+        assert!(received.contains("Failed Check: disks"));
+        assert!(!received.contains("AUTH"));
+
+        // Plain text and HTML versions
+        assert!(received.contains("multipart/alternative"));
+        assert!(received.contains("Threshold: 20% free"));
+        assert!(received.contains("Disk <full>"));
+        assert!(received.contains("Disk &lt;full&gt;"));
+    }
+
+    #[test]
+    fn test_diagnostics_attachment() {
+        let (port, server) = fake_smtp_server();
+        let conf = smtp_conf(port);
+        let mut notification = notification();
+        notification.diagnostics.push(crate::alerts::Diagnostic {
+            title: "Load average".to_string(),
+            lines: vec!["0.50 0.40 0.30".to_string()],
+        });
+
+        send(&conf, &notification, &["ops@example.com".to_string()]).unwrap();
+
+        let received = server.join().unwrap();
+        assert!(received.contains("multipart/mixed"));
+        assert!(received.contains("filename=\"diagnostics.txt\""));
+        assert!(received.contains("0.50 0.40 0.30"));
+    }
+
+    #[test]
+    fn test_custom_templates() {
+        let (port, server) = fake_smtp_server();
+        let mut conf = smtp_conf(port);
+        conf.templates.subject = Some("{{failure_count}} problem(s) on {{host}}".to_string());
+        conf.templates.text = Some("{{#each failures}}{{entry}} is at {{value}}{{/each}}".to_string());
+
+        send(&conf, &notification(), &["ops@example.com".to_string()]).unwrap();
+
+        let received = server.join().unwrap();
+        assert!(received.contains("Subject: 1 problem(s) on"));
+        assert!(received.contains("/ is at 10 MB free of 100 MB"));
+    }
+
+    #[test]
+    fn test_connection_failure() {
+        // Bind and drop a listener to get a port that nothing is listening on
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let conf = smtp_conf(port);
+
+        let result = send(&conf, &notification(), &["ops@example.com".to_string()]);
+        assert!(matches!(result, Err(AlertError::Delivery(_))));
+    }
+
+    #[test]
+    fn test_bad_contacts() {
+        let (port, server) = fake_smtp_server();
+        let conf = smtp_conf(port);
+
+        // A bad contact doesn't stop the others getting the alert
+        let contacts = ["not an address".to_string(), "ops@example.com".to_string()];
+        send(&conf, &notification(), &contacts).unwrap();
+        assert!(server.join().unwrap().contains("RCPT TO:<ops@example.com>"));
+
+        let result = send(&conf, &notification(), &["not an address".to_string()]);
+        assert!(matches!(result, Err(AlertError::Message(_))));
+
+        let mut conf = smtp_conf(port);
+        conf.from_address = "bad".to_string();
+        let result = send(&conf, &notification(), &["ops@example.com".to_string()]);
+        assert!(matches!(result, Err(AlertError::Config(_))));
+    }
+
+    #[test]
+    fn test_build_mailer() {
+        let mut conf = smtp_conf(25);
+        conf.tls = TlsMode::Starttls;
+        assert!(build_mailer(&conf).is_ok());
+
+        conf.timeout = Some("soon".to_string());
+        assert!(build_mailer(&conf).is_err());
+
+        conf.smtp = String::new();
+        assert!(build_mailer(&conf).is_err());
+
+        conf.transport = EmailTransport::Sendmail;
+        assert!(matches!(build_mailer(&conf), Ok(Mailer::Sendmail(_))));
     }
 }
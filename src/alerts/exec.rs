@@ -0,0 +1,117 @@
+use std::time::Duration;
+
+use super::{AlertError, Notification};
+use crate::rules::ExecAlert;
+use crate::{duration, log, process};
+
+/// How long the command can run when the alert doesn't set a `timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Run the alert's command with the notification as environment variables and JSON on stdin
+///
+/// The command's output is logged, a non-zero exit code fails the alert.
+pub fn alert(exec: &ExecAlert, notification: &Notification) -> Result<(), AlertError> {
+    let command = &exec.command;
+    let timeout = timeout(exec).map_err(AlertError::Config)?;
+    let stdin = serde_json::to_string(notification).map_err(|e| AlertError::Message(e.to_string()))?;
+
+    let output =
+        process::run(command, &exec.args, &env(notification), &stdin, timeout).map_err(AlertError::Delivery)?;
+
+    for line in output.stdout.lines().filter(|l| !l.trim().is_empty()) {
+        log::info(format!("{}: {}", command, line));
+    }
+    for line in output.stderr.lines().filter(|l| !l.trim().is_empty()) {
+        log::warn(format!("{}: {}", command, line));
+    }
+
+    if !output.success() {
+        let code = output.code.map_or("a signal".to_string(), |c| c.to_string());
+        return Err(AlertError::Delivery(format!("'{}' exited with {}", command, code)));
+    }
+    log::info(format!("Exec alert ran {}", command));
+
+    Ok(())
+}
+
+/// Check the exec settings of an alert
+pub fn validate(exec: &ExecAlert) -> Result<(), String> {
+    if exec.command.is_empty() {
+        return Err("exec alerts need a command".to_string());
+    }
+    timeout(exec)?;
+
+    Ok(())
+}
+
+fn timeout(exec: &ExecAlert) -> Result<Duration, String> {
+    match &exec.timeout {
+        None => Ok(DEFAULT_TIMEOUT),
+        Some(t) => duration::parse(t).ok_or(format!("invalid timeout: {}", t)),
+    }
+}
+
+/// Environment variables describing the notification
+///
+/// The details of each failure are only in the JSON, entries are joined with new lines.
+fn env(notification: &Notification) -> Vec<(String, String)> {
+    let entries: Vec<&str> = notification.failures.iter().map(|f| f.entry.as_str()).collect();
+
+    [
+        ("EEKWATCH_RULE", notification.rule.clone()),
+        ("EEKWATCH_HOST", notification.host.clone()),
+        ("EEKWATCH_SEVERITY", notification.severity.to_string()),
+        ("EEKWATCH_TIMESTAMP", notification.timestamp.to_rfc3339()),
+        ("EEKWATCH_FAILURE_COUNT", notification.failures.len().to_string()),
+        ("EEKWATCH_ENTRIES", entries.join("\n")),
+        ("EEKWATCH_TITLE", notification.title()),
+        ("EEKWATCH_MESSAGE", notification.text()),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v))
+    .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::alerts::test_notification;
+    use crate::rules::Severity;
+    use serde_json::json;
+    use std::{env, fs};
+
+    fn notification() -> Notification {
+        test_notification(&[("/", Severity::Critical)])
+    }
+
+    #[test]
+    fn test_exec() {
+        let out = env::temp_dir().join(format!("eekwatch-exec-{}", std::process::id()));
+        let script = format!(
+            "echo \"$EEKWATCH_RULE $EEKWATCH_SEVERITY $EEKWATCH_ENTRIES\" > {0}; cat >> {0}",
+            out.display()
+        );
+        let alert: ExecAlert = serde_json::from_value(json!({"command": "sh", "args": ["-c", script]})).unwrap();
+
+        super::alert(&alert, &notification()).unwrap();
+
+        let written = fs::read_to_string(&out).unwrap();
+        let (vars, stdin) = written.split_once('\n').unwrap();
+        assert_eq!(vars, "disks critical /");
+        let stdin: serde_json::Value = serde_json::from_str(stdin).unwrap();
+        assert_eq!(stdin["failures"][0]["value"], "10 MB free of 100 MB");
+
+        fs::remove_file(out).unwrap();
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let alert: ExecAlert =
+            serde_json::from_value(json!({"command": "sh", "args": ["-c", "exit 2"], "timeout": "5s"})).unwrap();
+        let result = super::alert(&alert, &notification());
+        assert!(matches!(result, Err(AlertError::Delivery(e)) if e.contains("exited with 2")));
+
+        let alert: ExecAlert = serde_json::from_value(json!({"command": "sh", "timeout": "soon"})).unwrap();
+        assert!(validate(&alert).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use super::AlertError;
+use crate::log;
+use std::{thread, time::Duration};
+
+/// How long a single request can take
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Wait before the first retry, doubled after each attempt
+const BACKOFF: Duration = Duration::from_secs(1);
+
+/// POST a body to a URL, retrying on connection errors and server errors
+///
+/// Client errors (4xx other than 429) aren't retried since sending the same request again won't help.
+/// Only the scheme and host of the URL are shown in errors and log messages, and any of `secrets`
+/// are replaced, since URLs such as Slack webhooks and Telegram's API have keys in them.
+pub fn post(
+    url: &str,
+    headers: &[(String, String)],
+    body: &str,
+    retries: u32,
+    secrets: &[&str],
+) -> Result<String, AlertError> {
+    send("POST", url, headers, body, retries, secrets)
+}
+
+/// Send a body to a URL with any method, retrying the same way as `post`
+pub fn send(
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: &str,
+    retries: u32,
+    secrets: &[&str],
+) -> Result<String, AlertError> {
+    send_with_backoff(method, url, headers, body, retries, secrets, BACKOFF)
+}
+
+fn send_with_backoff(
+    method: &str,
+    url: &str,
+    headers: &[(String, String)],
+    body: &str,
+    retries: u32,
+    secrets: &[&str],
+    backoff: Duration,
+) -> Result<String, AlertError> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let mut delay = backoff;
+    let mut attempt = 0;
+
+    loop {
+        let mut request = agent.request(method, url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+
+        let (error, retry) = match request.send_string(body) {
+            Ok(response) => return Ok(response.into_string().unwrap_or_default()),
+            Err(ureq::Error::Status(code, response)) => (
+                format!("{} returned {}: {}", url, code, response.into_string().unwrap_or_default()),
+                code >= 500 || code == 429,
+            ),
+            Err(e) => (e.to_string(), true),
+        };
+        let error = redact(&error, url, secrets);
+
+        if !retry || attempt >= retries {
+            return Err(AlertError::Delivery(error));
+        }
+
+        attempt += 1;
+        log::warn(format!(
+            "Request failed, retrying in {}s ({} of {}): {}",
+            delay.as_secs_f32(),
+            attempt,
+            retries,
+            error
+        ));
+        thread::sleep(delay);
+        delay *= 2;
+    }
+}
+
+/// Cut the URL down to its scheme and host, and hide the secrets
+fn redact(msg: &str, url: &str, secrets: &[&str]) -> String {
+    let mut msg = msg.replace(url, &display_url(url));
+    for secret in secrets.iter().filter(|s| !s.is_empty()) {
+        msg = msg.replace(secret, "[REDACTED]");
+    }
+    msg
+}
+
+/// The scheme and host of a URL, without any user info, path or query
+pub(crate) fn display_url(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit('@').next().unwrap_or_default();
+
+    if scheme.is_empty() {
+        host.to_string()
+    } else {
+        format!("{}://{}", scheme, host)
+    }
+}
+
+/// Percent encode a URL path segment, such as a `!room:server` ID
+pub fn encode_path(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// A local HTTP server for testing alert channels
+#[cfg(test)]
+pub mod test_server {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// A request received by the test server
+    #[derive(Debug, Default)]
+    pub struct Request {
+        pub method: String,
+        pub path: String,
+        pub headers: Vec<(String, String)>,
+        pub body: String,
+    }
+
+    impl Request {
+        pub fn header(&self, name: &str) -> Option<&str> {
+            self.headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.as_str())
+        }
+    }
+
+    /// Answer one request per status code and return the requests received
+    pub fn serve(statuses: &[u16]) -> (String, thread::JoinHandle<Vec<Request>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let statuses = statuses.to_vec();
+
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut writer = stream.try_clone().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = Request::default();
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut parts = line.split_whitespace();
+                request.method = parts.next().unwrap_or_default().to_string();
+                request.path = parts.next().unwrap_or_default().to_string();
+
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        request.headers.push((name.to_string(), value.trim().to_string()));
+                    }
+                }
+
+                let length: usize = request.header("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                request.body = String::from_utf8(body).unwrap();
+                requests.push(request);
+
+                let reply = format!("HTTP/1.1 {} Test\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok", status);
+                writer.write_all(reply.as_bytes()).unwrap();
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry() {
+        let (url, server) = test_server::serve(&[503, 500, 200]);
+        send_with_backoff("POST", &url, &[], "hi", 2, &[], Duration::from_millis(1)).unwrap();
+        assert_eq!(server.join().unwrap().len(), 3);
+
+        // Gives up after the retries are used
+        let (url, server) = test_server::serve(&[503, 503]);
+        let result = send_with_backoff("POST", &url, &[], "hi", 1, &[], Duration::from_millis(1));
+        assert!(matches!(result, Err(AlertError::Delivery(_))));
+        server.join().unwrap();
+
+        // Client errors aren't retried
+        let (url, server) = test_server::serve(&[400]);
+        let result = send_with_backoff("POST", &url, &[], "hi", 3, &[], Duration::from_millis(1));
+        assert!(matches!(result, Err(AlertError::Delivery(_))));
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_secrets_not_logged() {
+        let token = "123456:secret-token";
+
+        // Server errors, then a connection that's refused once the server has gone
+        let (url, server) = test_server::serve(&[503, 503]);
+        let url = format!("{}/bot{}/sendMessage", url, token);
+        let logged = log::capture(|| {
+            let result = send_with_backoff("POST", &url, &[], "hi", 1, &[token], Duration::from_millis(1));
+            let error = result.unwrap_err().to_string();
+            assert!(!error.contains(token), "{}", error);
+        });
+        server.join().unwrap();
+
+        assert_eq!(logged.len(), 1);
+        assert!(logged.iter().all(|l| !l.contains(token) && !l.contains("secret")), "{:?}", logged);
+
+        let url = url.replace("http://127.0.0.1", "http://user:pw@127.0.0.1");
+        let logged = log::capture(|| {
+            let _ = send_with_backoff("POST", &url, &[], "hi", 2, &[], Duration::from_millis(1));
+        });
+        assert_eq!(logged.len(), 2);
+        assert!(logged.iter().all(|l| !l.contains(token) && !l.contains("pw")), "{:?}", logged);
+    }
+
+    #[test]
+    fn test_display_url() {
+        assert_eq!(display_url("https://hooks.slack.com/services/T0/B0/xyz"), "https://hooks.slack.com");
+        assert_eq!(display_url("http://user:pw@localhost:8080/a?b=c"), "http://localhost:8080");
+    }
+
+    #[test]
+    fn test_encode_path() {
+        assert_eq!(encode_path("!room:example.com"), "%21room%3Aexample.com");
+        assert_eq!(encode_path("a/b c"), "a%2Fb%20c");
+    }
+}
@@ -0,0 +1,273 @@
+use serde_json::{json, Value};
+
+use super::{host_name, http, truncate, AlertError, Failure, Notification};
+use crate::log;
+use crate::rules::{IncidentAlert, Severity};
+
+/// Alert types that open an incident and close it when the check recovers
+pub const INCIDENT_TYPES: &[&str] = &["pagerduty", "opsgenie"];
+
+const PAGERDUTY_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+const OPSGENIE_URL: &str = "https://api.opsgenie.com";
+
+/// Retries used when the alert doesn't set any
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Open or update an incident for each failing entry
+pub fn trigger(alert_type: &str, incident: &IncidentAlert, notification: &Notification) -> Result<(), AlertError> {
+    let key = routing_key(alert_type, incident)?;
+    let retries = incident.retries.unwrap_or(DEFAULT_RETRIES);
+
+    let mut errors = Vec::new();
+    for failure in &notification.failures {
+        let dedup_key = dedup_key(&notification.host, &notification.rule, &failure.entry);
+        let sent = match alert_type {
+            "pagerduty" => {
+                let body = pagerduty_trigger(key, &dedup_key, notification, failure);
+                http::post(pagerduty_url(incident), &json_headers(), &body.to_string(), retries, &[])
+            }
+            "opsgenie" => {
+                let url = format!("{}/v2/alerts", opsgenie_url(incident));
+                let body = opsgenie_create(&dedup_key, notification, failure);
+                http::post(&url, &opsgenie_headers(key), &body.to_string(), retries, &[])
+            }
+            _ => return Err(AlertError::Config(format!("unknown incident type '{}'", alert_type))),
+        };
+
+        // An entry that fails doesn't stop the others
+        match sent {
+            Ok(_) => log::info(format!("{} incident triggered for {}", alert_type, dedup_key)),
+            Err(e) => errors.push(format!("{}: {}", failure.entry, e)),
+        }
+    }
+
+    all_sent(errors)
+}
+
+/// Close the incidents of entries that have recovered
+pub fn resolve(alert_type: &str, incident: &IncidentAlert, rule: &str, entries: &[String]) -> Result<(), AlertError> {
+    let key = routing_key(alert_type, incident)?;
+    let retries = incident.retries.unwrap_or(DEFAULT_RETRIES);
+    let host = host_name();
+
+    let mut errors = Vec::new();
+    for entry in entries {
+        let dedup_key = dedup_key(&host, rule, entry);
+        let sent = match alert_type {
+            "pagerduty" => {
+                let body = json!({
+                    "routing_key": key,
+                    "event_action": "resolve",
+                    "dedup_key": dedup_key,
+                });
+                http::post(pagerduty_url(incident), &json_headers(), &body.to_string(), retries, &[])
+            }
+            "opsgenie" => {
+                let url = format!(
+                    "{}/v2/alerts/{}/close?identifierType=alias",
+                    opsgenie_url(incident),
+                    http::encode_path(&dedup_key)
+                );
+                let body = json!({
+                    "source": "eekwatch",
+                    "note": format!("Rule '{}' recovered for '{}'", rule, entry),
+                });
+                http::post(&url, &opsgenie_headers(key), &body.to_string(), retries, &[])
+            }
+            _ => return Err(AlertError::Config(format!("unknown incident type '{}'", alert_type))),
+        };
+
+        match sent {
+            Ok(_) => log::info(format!("{} incident resolved for {}", alert_type, dedup_key)),
+            Err(e) => errors.push(format!("{}: {}", entry, e)),
+        }
+    }
+
+    all_sent(errors)
+}
+
+/// One error naming the entries that failed, if any did
+fn all_sent(errors: Vec<String>) -> Result<(), AlertError> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AlertError::Delivery(errors.join(", ")))
+    }
+}
+
+/// Same key for every alert about an entry so repeated alerts update the open incident
+fn dedup_key(host: &str, rule: &str, entry: &str) -> String {
+    format!("eekwatch/{}/{}/{}", host, rule, entry)
+}
+
+fn routing_key<'a>(alert_type: &str, incident: &'a IncidentAlert) -> Result<&'a str, AlertError> {
+    incident
+        .routing_key
+        .as_ref()
+        .map(|k| k.expose())
+        .ok_or(AlertError::Config(format!("{} alerts need a routing_key", alert_type)))
+}
+
+fn json_headers() -> Vec<(String, String)> {
+    vec![("Content-Type".to_string(), "application/json".to_string())]
+}
+
+fn opsgenie_headers(key: &str) -> Vec<(String, String)> {
+    let mut headers = json_headers();
+    headers.push(("Authorization".to_string(), format!("GenieKey {}", key)));
+    headers
+}
+
+/// The alert's `url` replaces the Events API endpoint
+fn pagerduty_url(incident: &IncidentAlert) -> &str {
+    incident.url.as_deref().unwrap_or(PAGERDUTY_URL)
+}
+
+/// The alert's `url` replaces the API base, e.g. for the EU instance
+fn opsgenie_url(incident: &IncidentAlert) -> &str {
+    incident.url.as_deref().unwrap_or(OPSGENIE_URL).trim_end_matches('/')
+}
+
+fn summary(notification: &Notification, failure: &Failure) -> String {
+    format!(
+        "[{}] {} - {} failed for {}",
+        failure.severity, notification.host, notification.rule, failure.entry
+    )
+}
+
+fn details(failure: &Failure) -> Value {
+    json!({
+        "value": failure.value,
+        "threshold": failure.threshold,
+        "failing_since": failure.failing_since,
+        "message": failure.msg.join("\n"),
+    })
+}
+
+fn pagerduty_trigger(key: &str, dedup_key: &str, notification: &Notification, failure: &Failure) -> Value {
+    json!({
+        "routing_key": key,
+        "event_action": "trigger",
+        "dedup_key": dedup_key,
+        "payload": {
+            "summary": truncate(summary(notification, failure), 1024),
+            "source": notification.host,
+            "severity": failure.severity.to_string(),
+            "timestamp": notification.timestamp.to_rfc3339(),
+            "component": failure.entry,
+            "group": notification.rule,
+            "custom_details": details(failure),
+        },
+    })
+}
+
+fn opsgenie_create(dedup_key: &str, notification: &Notification, failure: &Failure) -> Value {
+    let priority = match failure.severity {
+        Severity::Info => "P5",
+        Severity::Warning => "P3",
+        Severity::Critical => "P1",
+    };
+
+    json!({
+        "message": truncate(summary(notification, failure), 130),
+        "alias": dedup_key,
+        "description": truncate(failure.details(""), 15000),
+        "priority": priority,
+        "source": notification.host,
+        "entity": failure.entry,
+        "tags": ["eekwatch", notification.rule],
+        "details": {
+            "rule": notification.rule,
+            "entry": failure.entry,
+            "value": failure.value.as_deref().unwrap_or(""),
+            "threshold": failure.threshold.as_deref().unwrap_or(""),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::http::test_server;
+    use crate::alerts::test_notification;
+
+    fn notification() -> Notification {
+        test_notification(&[("/", Severity::Critical), ("/data", Severity::Warning)])
+    }
+
+    fn alert(url: &str) -> IncidentAlert {
+        serde_json::from_value(json!({"url": url, "routing_key": "key123", "retries": 0})).unwrap()
+    }
+
+    fn body(request: &test_server::Request) -> Value {
+        serde_json::from_str(&request.body).unwrap()
+    }
+
+    #[test]
+    fn test_pagerduty() {
+        let (url, server) = test_server::serve(&[202, 202, 202]);
+        let alert = alert(&url);
+        let notification = notification();
+
+        trigger("pagerduty", &alert, &notification).unwrap();
+        resolve("pagerduty", &alert, "disks", &["/".to_string()]).unwrap();
+
+        let requests = server.join().unwrap();
+        let triggered = body(&requests[0]);
+        assert_eq!(triggered["event_action"], "trigger");
+        assert_eq!(triggered["routing_key"], "key123");
+        assert_eq!(triggered["payload"]["severity"], "critical");
+        assert_eq!(body(&requests[1])["payload"]["component"], "/data");
+
+        // The resolve uses the same key as the trigger
+        let resolved = body(&requests[2]);
+        assert_eq!(resolved["event_action"], "resolve");
+        assert_eq!(resolved["dedup_key"], triggered["dedup_key"]);
+    }
+
+    #[test]
+    fn test_failed_entry() {
+        let (url, server) = test_server::serve(&[202, 500, 202]);
+        let notification = test_notification(&[
+            ("/", Severity::Critical),
+            ("/data", Severity::Warning),
+            ("/backup", Severity::Warning),
+        ]);
+
+        let error = trigger("pagerduty", &alert(&url), &notification).unwrap_err().to_string();
+
+        // The entries after the failed one still get an incident
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(body(&requests[2])["payload"]["component"], "/backup");
+        assert!(error.contains("/data: "), "{}", error);
+        assert!(!error.contains("/backup") && !error.contains("/:"), "{}", error);
+    }
+
+    #[test]
+    fn test_opsgenie() {
+        let (url, server) = test_server::serve(&[202, 202, 202]);
+        let alert = alert(&url);
+
+        trigger("opsgenie", &alert, &notification()).unwrap();
+        resolve("opsgenie", &alert, "disks", &["/".to_string()]).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].path, "/v2/alerts");
+        assert_eq!(requests[0].header("authorization"), Some("GenieKey key123"));
+        let created = body(&requests[0]);
+        assert_eq!(created["priority"], "P1");
+
+        let alias = http::encode_path(created["alias"].as_str().unwrap());
+        assert_eq!(
+            requests[2].path,
+            format!("/v2/alerts/{}/close?identifierType=alias", alias)
+        );
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let result = trigger("pagerduty", &IncidentAlert::default(), &notification());
+        assert!(matches!(result, Err(AlertError::Config(_))));
+    }
+}
@@ -1,14 +1,281 @@
-use std::fs::OpenOptions;
-
-use std::io::prelude::*;
+use chrono::{DateTime, Datelike, Local, SecondsFormat, Utc};
+use flate2::{write::GzEncoder, Compression};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 
+use super::{AlertError, Notification};
+use crate::commands::checks::CheckResult;
+use crate::config::{self, AlertLoggingConf, RotateWhen, RotationConf};
 use crate::log;
 
-pub fn write_to_log(file_path: &str, data: &str) {
-    match OpenOptions::new().create(true).append(true).open(file_path) {
+/// Daemon checks run in parallel, only one of them can write or rotate the file at a time
+static LOCK: Mutex<()> = Mutex::new(());
+
+/// Write the results of a rule check to the log file
+pub fn log_results(rule: &str, results: &[CheckResult]) {
+    for result in results {
+        let status = if result.failed { "failed" } else { "ok" };
+        let mut line = format!(
+            "check rule={} entry={} status={} severity={}",
+            quote(rule),
+            quote(&result.entry),
+            status,
+            result.severity
+        );
+        if let Some(value) = &result.value {
+            line.push_str(&format!(" value={}", quote(value)));
+        }
+        if let Some(threshold) = &result.threshold {
+            line.push_str(&format!(" threshold={}", quote(threshold)));
+        }
+        if result.failed {
+            line.push_str(&format!(" msg={}", quote(&result.msg.join(" - "))));
+        }
+
+        write(&line);
+    }
+}
+
+/// Write an alert, and whether it was sent, to the log file
+pub fn log_alert(name: &str, alert_type: &str, notification: &Notification, result: &Result<(), AlertError>) {
+    let entries: Vec<&str> = notification.failures.iter().map(|f| f.entry.as_str()).collect();
+    let mut line = format!(
+        "alert rule={} alert={} type={} severity={} entries={}",
+        quote(&notification.rule),
+        quote(name),
+        alert_type,
+        notification.severity,
+        quote(&entries.join(","))
+    );
+    match result {
+        Ok(_) => line.push_str(" status=sent"),
+        Err(e) => line.push_str(&format!(" status=failed error={}", quote(&e.to_string()))),
+    }
+
+    write(&line);
+}
+
+/// Write an alert that was held back to the log file, `channel` is the alert name and type when
+/// only that channel was held back
+pub fn log_suppressed(channel: Option<(&str, &str)>, notification: &Notification, reason: &str) {
+    let entries: Vec<&str> = notification.failures.iter().map(|f| f.entry.as_str()).collect();
+    let mut line = format!("alert rule={}", quote(&notification.rule));
+    if let Some((name, alert_type)) = channel {
+        line.push_str(&format!(" alert={} type={}", quote(name), alert_type));
+    }
+    line.push_str(&format!(
+        " severity={} entries={} status=suppressed reason={}",
+        notification.severity,
+        quote(&entries.join(",")),
+        quote(reason)
+    ));
+
+    write(&line);
+}
+
+/// Write an acknowledgement of a rule's failing entries to the log file
+pub fn log_ack(rule: &str, entries: &[String], comment: Option<&str>) {
+    let mut line = format!("ack rule={} entries={}", quote(rule), quote(&entries.join(",")));
+    if let Some(comment) = comment {
+        line.push_str(&format!(" comment={}", quote(comment)));
+    }
+
+    write(&line);
+}
+
+/// Add a timestamped line to the configured log file
+fn write(line: &str) {
+    if config::overrides().dry_run {
+        return;
+    }
+
+    let config = config::get();
+    write_to_log(&config.alerts.logging, line);
+}
+
+pub fn write_to_log(conf: &AlertLoggingConf, data: &str) {
+    let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    let path = conf.path();
+    let line = format!("{} {}\n", Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true), data);
+
+    if let Err(e) = rotate_if_needed(&path, &conf.rotation, line.len() as u64, Local::now()) {
+        log::error(format!("Failed to rotate log file {}: {}", path.display(), e));
+    }
+
+    match OpenOptions::new().create(true).append(true).open(&path) {
         Ok(mut file) => {
-            writeln!(file, "{}", data);
+            if let Err(e) = file.write_all(line.as_bytes()) {
+                log::error(format!("Failed to write to log file {}: {}", path.display(), e));
+            }
         }
-        Err(e) => log::error(format!("Failed to open log file: {}", e)),
+        Err(e) => log::error(format!("Failed to open log file {}: {}", path.display(), e)),
     };
 }
+
+fn rotate_if_needed(path: &Path, rotation: &RotationConf, adding: u64, now: DateTime<Local>) -> io::Result<()> {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    let limit = rotation
+        .limit_bytes()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let modified: DateTime<Local> = meta.modified()?.into();
+
+    if needs_rotation(rotation.when, limit, meta.len(), modified, adding, now) {
+        rotate(path, rotation)?;
+    }
+
+    Ok(())
+}
+
+fn needs_rotation(
+    when: RotateWhen,
+    limit: u64,
+    size: u64,
+    modified: DateTime<Local>,
+    adding: u64,
+    now: DateTime<Local>,
+) -> bool {
+    match when {
+        RotateWhen::Size => size > 0 && size + adding > limit,
+        RotateWhen::Daily => modified.date_naive() != now.date_naive(),
+        RotateWhen::Weekly => modified.iso_week() != now.iso_week(),
+        RotateWhen::Never => false,
+    }
+}
+
+/// Path of the nth old log file, e.g. eekwatch.log.2 or eekwatch.log.2.gz
+fn rotated(path: &Path, n: u32, gz: bool) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    if gz {
+        name.push(".gz");
+    }
+    PathBuf::from(name)
+}
+
+/// Move the old files along, dropping the oldest, and start a new file
+fn rotate(path: &Path, rotation: &RotationConf) -> io::Result<()> {
+    if rotation.keep == 0 {
+        return fs::remove_file(path);
+    }
+
+    for gz in [false, true] {
+        let oldest = rotated(path, rotation.keep, gz);
+        if oldest.exists() {
+            fs::remove_file(oldest)?;
+        }
+    }
+
+    for n in (1..rotation.keep).rev() {
+        for gz in [false, true] {
+            let from = rotated(path, n, gz);
+            if from.exists() {
+                fs::rename(from, rotated(path, n + 1, gz))?;
+            }
+        }
+    }
+
+    let first = rotated(path, 1, false);
+    fs::rename(path, &first)?;
+
+    if rotation.compress {
+        let mut encoder = GzEncoder::new(File::create(rotated(path, 1, true))?, Compression::default());
+        io::copy(&mut File::open(&first)?, &mut encoder)?;
+        encoder.finish()?;
+        fs::remove_file(first)?;
+    }
+
+    Ok(())
+}
+
+/// Quote a value when it has spaces or quotes in it
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        return value.to_string();
+    }
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+    use chrono::TimeZone;
+    use flate2::read::GzDecoder;
+
+    fn conf(dir: &Path, when: &str, compress: bool) -> AlertLoggingConf {
+        serde_json::from_value(serde_json::json!({
+            "location": dir,
+            "file": "eekwatch.log",
+            "rotation": {"when": when, "limit": "100B", "keep": 2, "compress": compress},
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_rotate_by_size() {
+        let dir = temp_dir("logging-size");
+        let conf = conf(&dir, "size", false);
+        let path = conf.path();
+
+        // Each line is over half the limit so every write after the first rotates
+        for i in 0..7 {
+            write_to_log(&conf, &format!("line {} {}", i, "x".repeat(30)));
+        }
+
+        assert!(fs::read_to_string(&path).unwrap().contains("line 6"));
+        assert!(fs::read_to_string(rotated(&path, 1, false)).unwrap().contains("line 5"));
+        assert!(fs::read_to_string(rotated(&path, 2, false)).unwrap().contains("line 4"));
+        // Only two old files are kept
+        assert!(!rotated(&path, 3, false).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_compress() {
+        let dir = temp_dir("logging-gzip");
+        let conf = conf(&dir, "size", true);
+        let path = conf.path();
+
+        write_to_log(&conf, &"a".repeat(80));
+        write_to_log(&conf, "second");
+
+        let mut old = String::new();
+        GzDecoder::new(File::open(rotated(&path, 1, true)).unwrap())
+            .read_to_string(&mut old)
+            .unwrap();
+        assert!(old.ends_with(&format!("{}\n", "a".repeat(80))));
+        assert!(!rotated(&path, 1, false).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_needs_rotation() {
+        let monday = Local.with_ymd_and_hms(2024, 6, 3, 9, 0, 0).unwrap();
+        let tuesday = Local.with_ymd_and_hms(2024, 6, 4, 9, 0, 0).unwrap();
+        let next_monday = Local.with_ymd_and_hms(2024, 6, 10, 9, 0, 0).unwrap();
+
+        assert!(!needs_rotation(RotateWhen::Daily, 0, 10, monday, 10, monday));
+        assert!(needs_rotation(RotateWhen::Daily, 0, 10, monday, 10, tuesday));
+        assert!(!needs_rotation(RotateWhen::Weekly, 0, 10, monday, 10, tuesday));
+        assert!(needs_rotation(RotateWhen::Weekly, 0, 10, monday, 10, next_monday));
+        assert!(needs_rotation(RotateWhen::Size, 100, 95, monday, 10, monday));
+        assert!(!needs_rotation(RotateWhen::Never, 100, 95, monday, 10, next_monday));
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("disks"), "disks");
+        assert_eq!(quote("10 MB"), "\"10 MB\"");
+        assert_eq!(quote("say \"hi\""), "\"say \\\"hi\\\"\"");
+        assert_eq!(quote(""), "\"\"");
+    }
+}
@@ -1,2 +1,506 @@
+use crate::rules::{Alert, Channel, Severity};
+use crate::template::{Context, Value};
+use crate::config::{self, ContactsConf};
+use crate::log;
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::{collections::HashMap, fmt};
+use sysinfo::System;
+
+pub mod bot;
+pub mod chat;
+pub mod contacts;
+pub mod digest;
 pub mod email;
+pub mod exec;
+pub mod http;
+pub mod incident;
 pub mod logging;
+pub mod push;
+pub mod quiet;
+pub mod rate_limit;
+pub mod syslog;
+pub mod webhook;
+
+/// What an alert is about
+#[derive(Serialize, Debug, Clone)]
+pub struct Notification {
+    pub rule: String,
+    pub host: String,
+    /// Highest severity of the failures
+    pub severity: Severity,
+    pub timestamp: DateTime<Utc>,
+    pub failures: Vec<Failure>,
+    /// Details gathered when alerting to help find the cause
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub diagnostics: Vec<Diagnostic>,
+    /// Failures from several rules sent together, `rule` lists the rules
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub digest: bool,
+}
+
+/// A titled section of diagnostic output, such as the largest directories on a disk
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+impl Diagnostic {
+    /// The title followed by the lines
+    pub fn text(&self) -> String {
+        let mut text = self.title.clone();
+        for line in &self.lines {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text
+    }
+}
+
+/// A failing rule entry
+#[derive(Serialize, Debug, Clone)]
+pub struct Failure {
+    pub entry: String,
+    pub severity: Severity,
+    /// Measured value, such as the free space on a disk
+    pub value: Option<String>,
+    /// Limit the value was checked against
+    pub threshold: Option<String>,
+    pub failing_since: Option<DateTime<Utc>>,
+    /// When the entry started alerting, used for escalation
+    pub alerting_since: Option<DateTime<Utc>>,
+    /// Message lines describing the failure
+    pub msg: Vec<String>,
+}
+
+impl Failure {
+    /// Value, threshold, failing since and the message as lines of text, `bold` wraps the labels
+    pub fn details(&self, bold: &str) -> String {
+        let mut lines = Vec::new();
+        if let Some(value) = &self.value {
+            lines.push(format!("{b}Value:{b} {}", value, b = bold));
+        }
+        if let Some(threshold) = &self.threshold {
+            lines.push(format!("{b}Threshold:{b} {}", threshold, b = bold));
+        }
+        if let Some(since) = self.failing_since {
+            lines.push(format!("{b}Failing since:{b} {}", since.to_rfc2822(), b = bold));
+        }
+        lines.extend(self.msg.iter().cloned());
+        lines.join("\n")
+    }
+}
+
+impl Notification {
+    pub fn new(rule: &str, failures: Vec<Failure>) -> Notification {
+        Notification {
+            rule: rule.to_string(),
+            host: host_name(),
+            severity: failures.iter().map(|f| f.severity).max().unwrap_or_default(),
+            timestamp: Utc::now(),
+            failures,
+            diagnostics: Vec::new(),
+            digest: false,
+        }
+    }
+
+    /// Short summary used as the alert title
+    pub fn title(&self) -> String {
+        if self.digest {
+            return format!(
+                "[{}] {} - {} Failed Checks ({})",
+                self.severity,
+                self.host,
+                self.failures.len(),
+                self.severity_counts()
+            );
+        }
+        format!("[{}] {} - Failed Check: {}", self.severity, self.host, self.rule)
+    }
+
+    /// Number of failures of each severity, most severe first, e.g. "2 critical, 1 warning"
+    pub fn severity_counts(&self) -> String {
+        let mut counts: Vec<(Severity, usize)> = Vec::new();
+        for failure in &self.failures {
+            match counts.iter_mut().find(|(s, _)| *s == failure.severity) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((failure.severity, 1)),
+            }
+        }
+        counts.sort_by_key(|(s, _)| std::cmp::Reverse(*s));
+
+        counts
+            .iter()
+            .map(|(s, n)| format!("{} {}", n, s))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// The failures as plain text, one paragraph each, followed by the diagnostics
+    pub fn text(&self) -> String {
+        self.failures
+            .iter()
+            .map(|f| format!("{} ({})\n{}", f.entry, f.severity, f.details("")))
+            .chain(self.diagnostics.iter().map(Diagnostic::text))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Values available to alert templates
+    pub fn context(&self) -> Context {
+        let text = |v: &str| Value::Text(v.to_string());
+
+        let failures = self
+            .failures
+            .iter()
+            .map(|f| {
+                Context::from([
+                    ("entry".to_string(), text(&f.entry)),
+                    ("severity".to_string(), text(&f.severity.to_string())),
+                    ("value".to_string(), text(f.value.as_deref().unwrap_or(""))),
+                    ("threshold".to_string(), text(f.threshold.as_deref().unwrap_or(""))),
+                    (
+                        "since".to_string(),
+                        text(&f.failing_since.map(|s| s.to_rfc2822()).unwrap_or_default()),
+                    ),
+                    ("message".to_string(), text(&f.msg.join("\n"))),
+                ])
+            })
+            .collect();
+
+        let diagnostics = self
+            .diagnostics
+            .iter()
+            .map(|d| {
+                Context::from([
+                    ("title".to_string(), text(&d.title)),
+                    ("text".to_string(), text(&d.lines.join("\n"))),
+                ])
+            })
+            .collect();
+
+        Context::from([
+            ("title".to_string(), text(&self.title())),
+            ("rule".to_string(), text(&self.rule)),
+            ("host".to_string(), text(&self.host)),
+            ("severity".to_string(), text(&self.severity.to_string())),
+            ("timestamp".to_string(), text(&self.timestamp.to_rfc2822())),
+            ("failure_count".to_string(), text(&self.failures.len().to_string())),
+            ("severity_counts".to_string(), text(&self.severity_counts())),
+            ("failures".to_string(), Value::List(failures)),
+            ("diagnostics".to_string(), Value::List(diagnostics)),
+        ])
+    }
+}
+
+/// Name of this host as shown in alerts
+pub fn host_name() -> String {
+    System::host_name().unwrap_or("UNKNOWN".to_string())
+}
+
+/// Cut text down to a length limit, marking where it was cut
+pub fn truncate(text: String, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text;
+    }
+    let mut cut: String = text.chars().take(max - 1).collect();
+    cut.push('…');
+    cut
+}
+
+/// Error delivering an alert
+#[derive(Debug)]
+pub enum AlertError {
+    /// The alert channel config is missing or invalid
+    Config(String),
+    /// The alert message couldn't be built, such as a bad contact address
+    Message(String),
+    /// The alert couldn't be delivered
+    Delivery(String),
+}
+
+impl fmt::Display for AlertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertError::Config(e) => write!(f, "alert config error: {}", e),
+            AlertError::Message(e) => write!(f, "failed to build alert: {}", e),
+            AlertError::Delivery(e) => write!(f, "failed to deliver alert: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AlertError {}
+
+/// Send the notification through each of the rule's alert channels
+///
+/// With digests turned on the alerts are held back to be sent with other rules' alerts, except
+/// for incident channels which need an incident per entry.
+pub fn notify(notification: &Notification, alerts: &HashMap<String, Alert>) {
+    let mut names: Vec<&String> = alerts.keys().collect();
+    names.sort();
+    let digest = digest::enabled();
+
+    for name in names {
+        let alert = &alerts[name];
+        let alert_type = alert.alert_type();
+        if digest && !incident::INCIDENT_TYPES.contains(&alert_type) {
+            digest::add(name, alert, notification);
+            continue;
+        }
+
+        notify_channel(name, alert, notification);
+    }
+}
+
+/// Send the notification through a single alert channel
+///
+/// A channel that fails is logged and its fallback is tried. Channels, and contacts, in quiet hours
+/// are skipped, as are channels over their rate limit.
+pub fn notify_channel(name: &str, alert: &Alert, notification: &Notification) {
+    let quiet_hours = &config::get().quiet_hours;
+    let mut alert = alert;
+    let mut alert_name = name.to_string();
+
+    loop {
+        let alert_type = alert.alert_type();
+        if quiet::channel_quiet(quiet_hours, &alert_name, alert_type, Local::now()) {
+            held_back(&alert_name, alert_type, notification, "in quiet hours");
+            break;
+        }
+
+        let result = match contacts::for_alert(&alert_name, alert, notification) {
+            Ok(recipients) if recipients.all_quiet() => {
+                held_back(&alert_name, alert_type, notification, "all contacts are in quiet hours");
+                break;
+            }
+            Ok(recipients) => {
+                if let Err(reason) = rate_limit::allow(&alert_name, alert_type, Utc::now()) {
+                    held_back(&alert_name, alert_type, notification, &reason);
+                    break;
+                }
+                if !recipients.quiet.is_empty() {
+                    log::info(format!(
+                        "Rule '{}' alert '{}' not sent to {} during quiet hours",
+                        notification.rule,
+                        alert_name,
+                        recipients.quiet.join(", ")
+                    ));
+                }
+                send(&alert_name, alert, &recipients.to, notification)
+            }
+            Err(e) => Err(AlertError::Config(e)),
+        };
+        logging::log_alert(&alert_name, alert_type, notification, &result);
+
+        match result {
+            Ok(_) => break,
+            Err(e) => {
+                log::error(format!(
+                    "Rule '{}' alert '{}' failed, {}",
+                    notification.rule, alert_name, e
+                ));
+
+                match &alert.fallback {
+                    Some(fallback) => {
+                        alert_name = format!("{} fallback", alert_name);
+                        alert = fallback;
+                        log::info(format!("Trying alert '{}'", alert_name));
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+fn held_back(name: &str, alert_type: &str, notification: &Notification, reason: &str) {
+    log::info(format!("Rule '{}' alert '{}' is {}, not sending", notification.rule, name, reason));
+    logging::log_suppressed(Some((name, alert_type)), notification, reason);
+}
+
+/// Send the notification through a single alert channel to the resolved contacts
+pub fn send(name: &str, alert: &Alert, contacts: &[String], notification: &Notification) -> Result<(), AlertError> {
+    let alert_type = alert.alert_type();
+
+    if config::overrides().dry_run {
+        log::info(format!("Dry run, not sending {} alert '{}'", alert_type, name));
+        return Ok(());
+    }
+
+    match &alert.channel {
+        Channel::Email {} => email::alert(notification, contacts),
+        Channel::Webhook(hook) => webhook::alert(hook, notification),
+        Channel::Slack(chat) | Channel::Discord(chat) | Channel::Teams(chat) => {
+            chat::alert(alert_type, chat, notification)
+        }
+        Channel::Ntfy(ntfy) => push::ntfy(ntfy, notification),
+        Channel::Gotify(http) => push::gotify(http, notification),
+        Channel::Pushover(http) => push::pushover(http, contacts, notification),
+        Channel::Telegram(http) => bot::telegram(http, contacts, notification),
+        Channel::Matrix(http) => bot::matrix(http, contacts, notification),
+        Channel::Pagerduty(incident) | Channel::Opsgenie(incident) => {
+            incident::trigger(alert_type, incident, notification)
+        }
+        Channel::Syslog {} => syslog::syslog(notification),
+        Channel::Journald {} => syslog::journald(notification),
+        Channel::Exec(exec) => exec::alert(exec, notification),
+    }
+}
+
+/// Close the incidents opened for entries that have recovered
+///
+/// Only incident channels, like PagerDuty, are sent recoveries. Fallbacks are resolved too since
+/// the incident may have been opened by one when the alert itself failed. Failures are logged.
+pub fn resolve(rule: &str, entries: &[String], alerts: &HashMap<String, Alert>) {
+    let mut names: Vec<&String> = alerts.keys().collect();
+    names.sort();
+
+    for name in names {
+        let mut alert = Some(&alerts[name]);
+        let mut alert_name = name.to_string();
+
+        while let Some(current) = alert {
+            resolve_channel(rule, entries, &alert_name, current);
+            alert = current.fallback.as_deref();
+            alert_name = format!("{} fallback", alert_name);
+        }
+    }
+}
+
+fn resolve_channel(rule: &str, entries: &[String], name: &str, alert: &Alert) {
+    let (Channel::Pagerduty(incident) | Channel::Opsgenie(incident)) = &alert.channel else {
+        return;
+    };
+    let alert_type = alert.alert_type();
+
+    if config::overrides().dry_run {
+        log::info(format!("Dry run, not resolving {} alert '{}'", alert_type, name));
+        return;
+    }
+
+    if let Err(e) = incident::resolve(alert_type, incident, rule, entries) {
+        log::error(format!("Rule '{}' alert '{}' failed to resolve, {}", rule, name, e));
+    }
+}
+
+/// Check an alert from a rule file is usable, `contacts` is the config its contacts refer to
+pub fn validate(alert: &Alert, contacts: &ContactsConf) -> Result<(), String> {
+    let alert_type = alert.alert_type();
+    match &alert.channel {
+        Channel::Webhook(hook) => webhook::validate(hook)?,
+        Channel::Exec(exec) => exec::validate(exec)?,
+        Channel::Slack(chat) | Channel::Discord(chat) | Channel::Teams(chat) if chat.url.is_empty() => {
+            return Err(format!("{} alerts need a url", alert_type));
+        }
+        Channel::Pagerduty(incident) | Channel::Opsgenie(incident) if incident.routing_key.is_none() => {
+            return Err(format!("{} alerts need a routing_key", alert_type));
+        }
+        Channel::Telegram(_) | Channel::Matrix(_) if alert.contacts.is_empty() && alert.escalation.is_empty() => {
+            return Err(format!("{} alerts need contacts", alert_type));
+        }
+        _ => {}
+    }
+    contacts::validate(alert, contacts)?;
+
+    if let Some(fallback) = &alert.fallback {
+        validate(fallback, contacts).map_err(|e| format!("fallback {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// A notification for `disks` with a failure for each entry, for the alert channel tests
+#[cfg(test)]
+pub(crate) fn test_notification(entries: &[(&str, Severity)]) -> Notification {
+    let failures = entries
+        .iter()
+        .map(|(entry, severity)| Failure {
+            entry: entry.to_string(),
+            severity: *severity,
+            value: Some("10 MB free of 100 MB".to_string()),
+            threshold: Some("20% free".to_string()),
+            failing_since: None,
+            alerting_since: None,
+            msg: vec![format!("Disk {} is low", entry)],
+        })
+        .collect();
+
+    Notification::new("disks", failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Alerts as they're read from a rule file
+    fn alerts(alerts: serde_json::Value) -> Result<HashMap<String, Alert>, String> {
+        let rule = serde_json::json!({"name": "disks", "rule_type": "disk", "rules": [], "alerts": alerts});
+        serde_json::from_value::<crate::rules::RuleConfig>(rule)
+            .map(|r| r.alerts)
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_alert_types() {
+        let parsed = alerts(serde_json::json!({
+            "email": {"contacts": ["ops@example.com"]},
+            "primary": {"alert_type": "email", "contacts": [], "fallback": {"alert_type": "email", "contacts": []}},
+            "hook": {"alert_type": "webhook", "url": "http://localhost", "retries": 1},
+        }))
+        .unwrap();
+        assert_eq!(parsed["email"].alert_type(), "email");
+        assert_eq!(parsed["hook"].alert_type(), "webhook");
+        assert!(parsed.values().all(|a| validate(a, &ContactsConf::default()).is_ok()));
+
+        let error = alerts(serde_json::json!({"pager": {"contacts": []}})).unwrap_err();
+        assert!(error.contains("alert 'pager': unknown variant `pager`"), "{}", error);
+
+        // Settings that belong to another type are rejected
+        let error = alerts(serde_json::json!({"email": {"contacts": [], "url": "http://localhost"}})).unwrap_err();
+        assert!(error.contains("unknown field `url`"), "{}", error);
+        let error = alerts(serde_json::json!({"webhook": {"url": "http://localhost", "topic": "disks"}})).unwrap_err();
+        assert!(error.contains("unknown field `topic`"), "{}", error);
+
+        // A fallback isn't named after a type so it needs one
+        let error = alerts(serde_json::json!({"email": {"fallback": {"contacts": []}}})).unwrap_err();
+        assert!(error.contains("missing field `alert_type`"), "{}", error);
+    }
+
+    #[test]
+    fn test_validate() {
+        let parsed = alerts(serde_json::json!({
+            "slack": {"url": ""},
+            "telegram": {},
+            "email": {"fallback": {"alert_type": "exec", "command": "notify", "timeout": "soon"}},
+        }))
+        .unwrap();
+
+        for name in ["slack", "telegram", "email"] {
+            assert!(validate(&parsed[name], &ContactsConf::default()).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_resolve_fallback() {
+        let (url, server) = http::test_server::serve(&[202]);
+        let parsed = alerts(serde_json::json!({
+            "email": {"contacts": [], "fallback": {
+                "alert_type": "pagerduty", "url": url, "routing_key": "key123", "retries": 0,
+            }},
+        }))
+        .unwrap();
+
+        resolve("disks", &["/".to_string()], &parsed);
+
+        let requests = server.join().unwrap();
+        let body: serde_json::Value = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(body["event_action"], "resolve");
+    }
+
+    #[test]
+    fn test_truncate() {
+        assert_eq!(truncate("abc".to_string(), 3), "abc");
+        assert_eq!(truncate("abcdef".to_string(), 4), "abc…");
+    }
+}
@@ -0,0 +1,248 @@
+use serde_json::json;
+
+use super::{http, truncate, AlertError, Notification};
+use crate::config::{self, AlertGotifyConf, AlertNtfyConf, AlertPushoverConf};
+use crate::log;
+use crate::rules::{HttpAlert, NtfyAlert, Severity};
+
+/// Retries used when the alert doesn't set any
+const DEFAULT_RETRIES: u32 = 3;
+
+/// Send the notification to an ntfy topic
+pub fn ntfy(ntfy: &NtfyAlert, notification: &Notification) -> Result<(), AlertError> {
+    let config = config::get();
+    let conf = config
+        .alerts
+        .ntfy
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.ntfy isn't set in the config".to_string()))?;
+
+    send_ntfy(conf, ntfy, notification)
+}
+
+/// Send the notification to a Gotify server
+pub fn gotify(alert: &HttpAlert, notification: &Notification) -> Result<(), AlertError> {
+    let config = config::get();
+    let conf = config
+        .alerts
+        .gotify
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.gotify isn't set in the config".to_string()))?;
+
+    send_gotify(conf, alert, notification)
+}
+
+/// Send the notification to Pushover users
+pub fn pushover(alert: &HttpAlert, contacts: &[String], notification: &Notification) -> Result<(), AlertError> {
+    let config = config::get();
+    let conf = config
+        .alerts
+        .pushover
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.pushover isn't set in the config".to_string()))?;
+
+    send_pushover(conf, alert, contacts, notification)
+}
+
+fn send_ntfy(conf: &AlertNtfyConf, ntfy: &NtfyAlert, notification: &Notification) -> Result<(), AlertError> {
+    let topic = ntfy
+        .topic
+        .as_ref()
+        .or(conf.topic.as_ref())
+        .ok_or(AlertError::Config("ntfy alerts need a topic in the rule or alerts.ntfy.topic".to_string()))?;
+
+    // ntfy priorities go from 1 (min) to 5 (max)
+    let (priority, tag) = match notification.severity {
+        Severity::Info => (3, "information_source"),
+        Severity::Warning => (4, "warning"),
+        Severity::Critical => (5, "rotating_light"),
+    };
+
+    // Publishing JSON to the server root avoids encoding the title in a header
+    let body = json!({
+        "topic": topic,
+        "title": notification.title(),
+        "message": truncate(notification.text(), 4000),
+        "priority": priority,
+        "tags": [tag],
+    });
+
+    let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+    if let Some(token) = &conf.token {
+        headers.push(("Authorization".to_string(), format!("Bearer {}", token.expose())));
+    }
+
+    let url = conf.server.trim_end_matches('/');
+    http::post(url, &headers, &body.to_string(), ntfy.retries.unwrap_or(DEFAULT_RETRIES), &[])?;
+    log::info(format!("ntfy alert sent to topic {}", topic));
+
+    Ok(())
+}
+
+fn send_gotify(conf: &AlertGotifyConf, alert: &HttpAlert, notification: &Notification) -> Result<(), AlertError> {
+    let token = conf
+        .token
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.gotify.token isn't set".to_string()))?;
+
+    // Gotify clients treat 1-3 as silent, 4-7 as normal and 8+ as high priority
+    let priority = match notification.severity {
+        Severity::Info => 3,
+        Severity::Warning => 6,
+        Severity::Critical => 9,
+    };
+
+    let body = json!({
+        "title": notification.title(),
+        "message": notification.text(),
+        "priority": priority,
+    });
+
+    let headers = vec![
+        ("Content-Type".to_string(), "application/json".to_string()),
+        ("X-Gotify-Key".to_string(), token.expose().to_string()),
+    ];
+
+    let url = format!("{}/message", conf.server.trim_end_matches('/'));
+    http::post(&url, &headers, &body.to_string(), alert.retries.unwrap_or(DEFAULT_RETRIES), &[])?;
+    log::info(format!("Gotify alert sent to {}", conf.server));
+
+    Ok(())
+}
+
+fn send_pushover(
+    conf: &AlertPushoverConf,
+    alert: &HttpAlert,
+    contacts: &[String],
+    notification: &Notification,
+) -> Result<(), AlertError> {
+    let token = conf
+        .token
+        .as_ref()
+        .ok_or(AlertError::Config("alerts.pushover.token isn't set".to_string()))?;
+
+    // The contacts are Pushover user or group keys
+    let users = if contacts.is_empty() {
+        conf.user.clone().into_iter().collect()
+    } else {
+        contacts.to_vec()
+    };
+    if users.is_empty() {
+        return Err(AlertError::Config(
+            "pushover alerts need contacts in the rule or alerts.pushover.user".to_string(),
+        ));
+    }
+
+    // -1 is quiet, 0 is normal and 1 is high priority that bypasses quiet hours
+    let priority = match notification.severity {
+        Severity::Info => -1,
+        Severity::Warning => 0,
+        Severity::Critical => 1,
+    };
+
+    let body = json!({
+        "token": token.expose(),
+        "user": users.join(","),
+        "title": truncate(notification.title(), 250),
+        "message": truncate(notification.text(), 1024),
+        "priority": priority,
+        "timestamp": notification.timestamp.timestamp(),
+    });
+
+    let headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+    http::post(&conf.api_url, &headers, &body.to_string(), alert.retries.unwrap_or(DEFAULT_RETRIES), &[])?;
+    log::info(format!("Pushover alert sent to {}", users.join(", ")));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::http::test_server;
+    use crate::alerts::test_notification;
+    use crate::secret::Secret;
+
+    fn notification(severity: Severity) -> Notification {
+        test_notification(&[("/", severity)])
+    }
+
+    fn alert() -> HttpAlert {
+        HttpAlert { retries: Some(0) }
+    }
+
+    fn body(request: &test_server::Request) -> serde_json::Value {
+        serde_json::from_str(&request.body).unwrap()
+    }
+
+    #[test]
+    fn test_ntfy() {
+        let (url, server) = test_server::serve(&[200, 200]);
+        let conf = AlertNtfyConf {
+            server: url,
+            topic: Some("default-topic".to_string()),
+            token: Some(Secret::new("tk_123".to_string())),
+            token_file: None,
+        };
+
+        // The rule's topic is used over the default
+        let ntfy = NtfyAlert {
+            topic: Some("disks".to_string()),
+            retries: Some(0),
+        };
+        send_ntfy(&conf, &ntfy, &notification(Severity::Critical)).unwrap();
+        let ntfy = NtfyAlert { topic: None, retries: Some(0) };
+        send_ntfy(&conf, &ntfy, &notification(Severity::Info)).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].header("authorization"), Some("Bearer tk_123"));
+        let critical = body(&requests[0]);
+        assert_eq!(critical["topic"], "disks");
+        assert_eq!(critical["priority"], 5);
+        assert!(critical["message"].as_str().unwrap().contains("Disk / is low"));
+
+        let info = body(&requests[1]);
+        assert_eq!(info["topic"], "default-topic");
+        assert_eq!(info["priority"], 3);
+    }
+
+    #[test]
+    fn test_gotify() {
+        let (url, server) = test_server::serve(&[200]);
+        let conf = AlertGotifyConf {
+            server: format!("{}/", url),
+            token: Some(Secret::new("app-token".to_string())),
+            token_file: None,
+        };
+
+        send_gotify(&conf, &alert(), &notification(Severity::Warning)).unwrap();
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.path, "/message");
+        assert_eq!(request.header("x-gotify-key"), Some("app-token"));
+        assert_eq!(body(request)["priority"], 6);
+    }
+
+    #[test]
+    fn test_pushover() {
+        let (url, server) = test_server::serve(&[200]);
+        let mut conf = AlertPushoverConf {
+            token: Some(Secret::new("app-token".to_string())),
+            token_file: None,
+            user: Some("default-user".to_string()),
+            api_url: url,
+        };
+
+        let contacts = vec!["user1".to_string(), "group2".to_string()];
+        send_pushover(&conf, &alert(), &contacts, &notification(Severity::Critical)).unwrap();
+
+        let sent = body(&server.join().unwrap()[0]);
+        assert_eq!(sent["token"], "app-token");
+        assert_eq!(sent["user"], "user1,group2");
+        assert_eq!(sent["priority"], 1);
+
+        conf.user = None;
+        let result = send_pushover(&conf, &alert(), &[], &notification(Severity::Info));
+        assert!(matches!(result, Err(AlertError::Config(_))));
+    }
+}
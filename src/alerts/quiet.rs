@@ -0,0 +1,107 @@
+use chrono::{DateTime, Datelike, Local, NaiveDateTime, Utc};
+
+use super::contacts;
+use crate::config::{ContactsConf, QuietHoursConf};
+
+/// Check if quiet hours are in effect at a local time
+pub fn is_active(period: &QuietHoursConf, now: NaiveDateTime) -> bool {
+    let time = now.time();
+    let today = now.weekday();
+    let starts_on = |day| period.days.is_empty() || period.days.contains(&day);
+
+    if period.start == period.end {
+        // The whole day
+        starts_on(today)
+    } else if period.start < period.end {
+        starts_on(today) && time >= period.start && time < period.end
+    } else {
+        // Runs past midnight, so it may have started yesterday
+        (starts_on(today) && time >= period.start) || (starts_on(today.pred()) && time < period.end)
+    }
+}
+
+fn applies_to(period: &QuietHoursConf, alert_name: &str, alert_type: &str) -> bool {
+    period.channels.is_empty() || period.channels.iter().any(|c| c == alert_name || c == alert_type)
+}
+
+/// Check if a whole alert channel is held back by quiet hours
+pub fn channel_quiet(periods: &[QuietHoursConf], alert_name: &str, alert_type: &str, now: DateTime<Local>) -> bool {
+    periods
+        .iter()
+        .any(|p| p.contacts.is_empty() && applies_to(p, alert_name, alert_type) && is_active(p, now.naive_local()))
+}
+
+/// Contacts in quiet hours for an alert channel, with groups and rotations expanded
+pub fn quiet_contacts(
+    periods: &[QuietHoursConf],
+    conf: &ContactsConf,
+    alert_name: &str,
+    alert_type: &str,
+    now: DateTime<Local>,
+) -> Result<Vec<String>, String> {
+    let mut quiet = Vec::new();
+
+    for period in periods {
+        if period.contacts.is_empty() || !applies_to(period, alert_name, alert_type) || !is_active(period, now.naive_local()) {
+            continue;
+        }
+        quiet.extend(contacts::expand(conf, &period.contacts, now.with_timezone(&Utc))?);
+    }
+
+    Ok(quiet)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    fn period(value: serde_json::Value) -> QuietHoursConf {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// 2024-06-03 is a Monday
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 6, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn test_overnight() {
+        let nights = period(json!({"start": "22:00", "end": "07:30", "days": ["fri"]}));
+
+        assert!(!is_active(&nights, at(7, 21, 59)));
+        assert!(is_active(&nights, at(7, 22, 0)));
+        // Saturday morning is still Friday night
+        assert!(is_active(&nights, at(8, 7, 29)));
+        assert!(!is_active(&nights, at(8, 7, 30)));
+        assert!(!is_active(&nights, at(8, 23, 0)));
+    }
+
+    #[test]
+    fn test_same_day() {
+        let lunch = period(json!({"start": "12:00", "end": "13:00"}));
+        assert!(is_active(&lunch, at(3, 12, 30)));
+        assert!(!is_active(&lunch, at(3, 13, 0)));
+
+        let weekend = period(json!({"start": "00:00", "end": "00:00", "days": ["Saturday", "sun"]}));
+        assert!(is_active(&weekend, at(9, 15, 0)));
+        assert!(!is_active(&weekend, at(10, 15, 0)));
+    }
+
+    #[test]
+    fn test_quiet_contacts() {
+        let conf: ContactsConf = serde_json::from_value(json!({"groups": {"day": ["ann@example.com"]}})).unwrap();
+        let periods = vec![
+            period(json!({"start": "00:00", "end": "00:00", "contacts": ["group:day"], "channels": ["email"]})),
+            period(json!({"start": "00:00", "end": "00:00", "channels": ["pager"]})),
+        ];
+        let now = Local::now();
+
+        assert_eq!(quiet_contacts(&periods, &conf, "primary", "email", now).unwrap(), vec!["ann@example.com"]);
+        assert!(quiet_contacts(&periods, &conf, "chat", "slack", now).unwrap().is_empty());
+
+        assert!(channel_quiet(&periods, "pager", "pushover", now));
+        assert!(!channel_quiet(&periods, "primary", "email", now));
+    }
+}
@@ -0,0 +1,77 @@
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+use crate::config::{self, RateLimitConf};
+use crate::duration;
+use crate::state::State;
+
+/// When each rate limited channel sent its recent alerts, shared by the daemon's checks
+static SENT: Mutex<BTreeMap<String, Vec<DateTime<Utc>>>> = Mutex::new(BTreeMap::new());
+
+/// Check a channel is under its rate limit and count the alert if it is, returns why it isn't
+pub fn allow(name: &str, alert_type: &str, now: DateTime<Utc>) -> Result<(), String> {
+    let limits = &config::get().alerts.rate_limits;
+    let Some((key, limit)) = limits.get_key_value(name).or_else(|| limits.get_key_value(alert_type)) else {
+        return Ok(());
+    };
+
+    let mut sent = SENT.lock().unwrap_or_else(PoisonError::into_inner);
+    check(&mut sent, key, limit, now)
+}
+
+fn check(
+    sent: &mut BTreeMap<String, Vec<DateTime<Utc>>>,
+    key: &str,
+    limit: &RateLimitConf,
+    now: DateTime<Utc>,
+) -> Result<(), String> {
+    let per = duration::parse(&limit.per)
+        .and_then(|p| chrono::Duration::from_std(p).ok())
+        .ok_or(format!("invalid rate limit period: {}", limit.per))?;
+
+    let times = sent.entry(key.to_string()).or_default();
+    times.retain(|t| now - *t < per);
+    if times.len() >= limit.max as usize {
+        return Err(format!("rate limited, {} alert(s) sent in the last {}", times.len(), limit.per));
+    }
+    times.push(now);
+
+    Ok(())
+}
+
+/// Pick up the alerts sent by earlier runs from the state
+pub fn restore(state: &State) {
+    *SENT.lock().unwrap_or_else(PoisonError::into_inner) = state.alerts_sent.clone();
+}
+
+/// Keep the alerts sent in the state so the limit carries on across runs
+pub fn store(state: &mut State) {
+    state.alerts_sent = SENT.lock().unwrap_or_else(PoisonError::into_inner).clone();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let limit = RateLimitConf {
+            max: 2,
+            per: "1h".to_string(),
+        };
+        let mut sent = BTreeMap::new();
+        let start = Utc::now();
+
+        assert!(check(&mut sent, "email", &limit, start).is_ok());
+        assert!(check(&mut sent, "email", &limit, start + chrono::Duration::minutes(10)).is_ok());
+        let result = check(&mut sent, "email", &limit, start + chrono::Duration::minutes(20));
+        assert_eq!(result, Err("rate limited, 2 alert(s) sent in the last 1h".to_string()));
+        // Other channels have their own count
+        assert!(check(&mut sent, "slack", &limit, start).is_ok());
+
+        // Once the first alert is an hour old there's room again
+        assert!(check(&mut sent, "email", &limit, start + chrono::Duration::minutes(60)).is_ok());
+        assert_eq!(sent["email"].len(), 2);
+    }
+}
@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use super::{AlertError, Failure, Notification};
+use crate::config;
+use crate::log;
+use crate::rules::Severity;
+use crate::sink::{self, Priority, SyslogConf};
+
+/// Send a syslog message for each failure, using the `log.syslog` settings
+pub fn syslog(notification: &Notification) -> Result<(), AlertError> {
+    let config = config::get();
+    send_syslog(&config.log.syslog, notification)?;
+    log::info(format!("Syslog alert sent to {}", config.log.syslog.address));
+
+    Ok(())
+}
+
+/// Write a journal entry for each failure
+pub fn journald(notification: &Notification) -> Result<(), AlertError> {
+    send_journal(Path::new(sink::JOURNALD_SOCKET), notification)?;
+    log::info("Journald alert sent".to_string());
+
+    Ok(())
+}
+
+fn priority(severity: Severity) -> Priority {
+    match severity {
+        Severity::Info => Priority::Info,
+        Severity::Warning => Priority::Warning,
+        Severity::Critical => Priority::Critical,
+    }
+}
+
+fn message(notification: &Notification, failure: &Failure) -> String {
+    let mut msg = format!("Rule '{}' failed for '{}'", notification.rule, failure.entry);
+    if !failure.msg.is_empty() {
+        msg = format!("{}: {}", msg, failure.msg.join(" - "));
+    }
+    msg
+}
+
+fn send_syslog(conf: &SyslogConf, notification: &Notification) -> Result<(), AlertError> {
+    for failure in &notification.failures {
+        let severity = failure.severity.to_string();
+        let mut data = vec![
+            ("rule", notification.rule.as_str()),
+            ("entry", failure.entry.as_str()),
+            ("severity", severity.as_str()),
+        ];
+        if let Some(value) = &failure.value {
+            data.push(("value", value));
+        }
+        if let Some(threshold) = &failure.threshold {
+            data.push(("threshold", threshold));
+        }
+
+        sink::send_syslog(
+            conf,
+            priority(failure.severity),
+            "alert",
+            &data,
+            &message(notification, failure),
+        )
+        .map_err(AlertError::Delivery)?;
+    }
+
+    Ok(())
+}
+
+fn send_journal(socket: &Path, notification: &Notification) -> Result<(), AlertError> {
+    for failure in &notification.failures {
+        let message = message(notification, failure);
+        let priority = (priority(failure.severity) as u8).to_string();
+        let severity = failure.severity.to_string();
+        let mut fields = vec![
+            ("MESSAGE", message.as_str()),
+            ("PRIORITY", priority.as_str()),
+            ("SYSLOG_IDENTIFIER", "eekwatch"),
+            ("RULE", notification.rule.as_str()),
+            ("ENTRY", failure.entry.as_str()),
+            ("SEVERITY", severity.as_str()),
+            ("HOST", notification.host.as_str()),
+        ];
+        if let Some(value) = &failure.value {
+            fields.push(("VALUE", value));
+        }
+        if let Some(threshold) = &failure.threshold {
+            fields.push(("THRESHOLD", threshold));
+        }
+
+        sink::send_journal(socket, &fields).map_err(AlertError::Delivery)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::alerts::test_notification;
+    use std::os::unix::net::UnixDatagram;
+    use std::{env, fs};
+
+    #[test]
+    fn test_alerts() {
+        let path = env::temp_dir().join(format!("eekwatch-alert-{}.sock", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        let notification = test_notification(&[("/", Severity::Critical)]);
+        let mut buf = vec![0; 4096];
+
+        let conf = SyslogConf {
+            address: path.display().to_string(),
+            ..SyslogConf::default()
+        };
+        send_syslog(&conf, &notification).unwrap();
+        let len = listener.recv(&mut buf).unwrap();
+        let msg = String::from_utf8_lossy(&buf[..len]).to_string();
+        // daemon (3) * 8 + critical (2)
+        assert!(msg.starts_with("<26>1 "));
+        assert!(msg.contains(
+            "[eekwatch@32473 rule=\"disks\" entry=\"/\" severity=\"critical\" value=\"10 MB free of 100 MB\" \
+             threshold=\"20% free\"] \
+             Rule 'disks' failed for '/': Disk / is low"
+        ));
+
+        send_journal(&path, &notification).unwrap();
+        let len = listener.recv(&mut buf).unwrap();
+        let entry = String::from_utf8_lossy(&buf[..len]).to_string();
+        assert!(entry.contains("PRIORITY=2\n"));
+        assert!(entry.contains("RULE=disks\n"));
+        assert!(entry.contains("SEVERITY=critical\n"));
+        assert!(entry.contains("VALUE=10 MB free of 100 MB\n"));
+        assert!(entry.contains("THRESHOLD=20% free\n"));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use super::{http, AlertError, Notification};
+use crate::log;
+use crate::rules::WebhookAlert;
+use crate::template::{Escape, Template};
+
+/// Header holding the HMAC-SHA256 signature of the body
+pub const SIGNATURE_HEADER: &str = "X-Eekwatch-Signature";
+
+/// Retries used when the alert doesn't set any
+const DEFAULT_RETRIES: u32 = 3;
+
+pub fn alert(hook: &WebhookAlert, notification: &Notification) -> Result<(), AlertError> {
+    let body = build_body(hook, notification)?;
+
+    let mut headers: Vec<(String, String)> = hook.headers.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+    if !has_header(hook, "content-type") {
+        headers.push(("Content-Type".to_string(), "application/json".to_string()));
+    }
+    if let Some(secret) = &hook.secret {
+        headers.push((SIGNATURE_HEADER.to_string(), sign(secret.expose(), &body)));
+    }
+
+    http::post(&hook.url, &headers, &body, hook.retries.unwrap_or(DEFAULT_RETRIES), &[])?;
+    log::info(format!("Webhook alert sent to {}", http::display_url(&hook.url)));
+
+    Ok(())
+}
+
+/// Check the webhook settings of an alert
+pub fn validate(hook: &WebhookAlert) -> Result<(), String> {
+    if hook.url.is_empty() {
+        return Err("webhook alerts need a url".to_string());
+    }
+
+    if let Some(template) = &hook.body_template {
+        Template::parse(template).map_err(|e| format!("invalid body_template: {}", e))?;
+    }
+
+    Ok(())
+}
+
+/// The request body, the notification as JSON unless a body template is set
+fn build_body(hook: &WebhookAlert, notification: &Notification) -> Result<String, AlertError> {
+    let Some(template) = &hook.body_template else {
+        return serde_json::to_string(notification).map_err(|e| AlertError::Message(e.to_string()));
+    };
+
+    let template = Template::parse(template)
+        .map_err(|e| AlertError::Config(format!("invalid body_template: {}", e)))?;
+
+    // Values are escaped for JSON unless another content type is sent
+    let is_json = hook
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+        .map_or(true, |(_, v)| v.contains("json"));
+    let escape = if is_json { Escape::Json } else { Escape::None };
+
+    Ok(template.render(&notification.context(), escape))
+}
+
+fn has_header(hook: &WebhookAlert, name: &str) -> bool {
+    hook.headers.keys().any(|k| k.eq_ignore_ascii_case(name))
+}
+
+/// Sign the body with HMAC-SHA256, formatted as `sha256=<hex digest>`
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::http::test_server;
+    use crate::alerts::test_notification;
+    use crate::rules::Severity;
+    use crate::secret::Secret;
+
+    fn notification() -> Notification {
+        test_notification(&[("/", Severity::Warning)])
+    }
+
+    fn webhook(url: &str) -> WebhookAlert {
+        serde_json::from_value(serde_json::json!({
+            "url": format!("{}/hook", url),
+            "headers": {"Authorization": "Bearer token"},
+            "retries": 0,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_payload() {
+        let (url, server) = test_server::serve(&[200]);
+        let mut hook = webhook(&url);
+        hook.secret = Some(Secret::new("key".to_string()));
+
+        alert(&hook, &notification()).unwrap();
+
+        let request = &server.join().unwrap()[0];
+        assert_eq!(request.path, "/hook");
+        assert_eq!(request.header("authorization"), Some("Bearer token"));
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        assert_eq!(request.header(SIGNATURE_HEADER), Some(sign("key", &request.body).as_str()));
+
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["rule"], "disks");
+        assert_eq!(body["severity"], "warning");
+        assert_eq!(body["failures"][0]["entry"], "/");
+        assert!(body["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_url_not_logged() {
+        let (url, server) = test_server::serve(&[200]);
+        let mut hook = webhook(&url);
+        hook.url = format!("{}/hooks/secret-path?token=secret-query", url);
+
+        let logged = log::capture(|| alert(&hook, &notification()).unwrap());
+        server.join().unwrap();
+
+        assert_eq!(logged.len(), 1);
+        assert!(logged[0].contains(&url), "{:?}", logged);
+        assert!(!logged[0].contains("secret"), "{:?}", logged);
+    }
+
+    #[test]
+    fn test_body_template() {
+        let (url, server) = test_server::serve(&[200]);
+        let mut hook = webhook(&url);
+        hook.body_template =
+            Some(r#"{"text": "{{#each failures}}{{message}}{{/each}}", "host": "{{host}}"}"#.to_string());
+
+        let mut notification = notification();
+        notification.failures[0].msg = vec!["Disk \"/\" is low".to_string()];
+        alert(&hook, &notification).unwrap();
+
+        let request = &server.join().unwrap()[0];
+        assert!(request.header(SIGNATURE_HEADER).is_none());
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["text"], "Disk \"/\" is low");
+    }
+
+    #[test]
+    fn test_sign() {
+        // Known HMAC-SHA256 test value
+        assert_eq!(
+            sign("key", "The quick brown fox jumps over the lazy dog"),
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut hook = webhook("http://localhost");
+        assert!(validate(&hook).is_ok());
+
+        hook.body_template = Some("{{#if rule}}".to_string());
+        assert!(validate(&hook).is_err());
+
+        hook.url = String::new();
+        assert!(validate(&hook).is_err());
+    }
+}
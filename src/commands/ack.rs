@@ -0,0 +1,22 @@
+use crate::alerts::logging;
+use crate::rules::get_single_rule;
+use crate::{log, state};
+use chrono::Utc;
+
+/// Acknowledge a rule's alerting entries so they stop alerting until they recover or get worse
+///
+/// The acknowledgement is kept in the state file so a running daemon picks it up on the rule's
+/// next check.
+pub fn ack(rule: &str, entries: &[String], comment: Option<String>) -> Result<(), String> {
+    let rule = get_single_rule(rule.to_string())?.name;
+    let _lock = state::lock();
+    let mut state = state::load();
+
+    let acknowledged = state.acknowledge(&rule, entries, comment.clone(), Utc::now())?;
+    logging::log_ack(&rule, &acknowledged, comment.as_deref());
+    log::info(format!("Acknowledged rule '{}' for {}", rule, acknowledged.join(", ")));
+
+    state::save(&state);
+
+    Ok(())
+}
@@ -1,6 +1,5 @@
-use crate::alerts::email::alert;
-use crate::rules::Alert;
-use crate::{log, rules::RuleConfig};
+use super::CheckResult;
+use crate::{log, rules::{RuleConfig, Severity}};
 use regex::Regex;
 use std::collections::HashMap;
 use sysinfo::{Disk, Disks};
@@ -18,14 +17,13 @@ struct Limit {
 }
 
 /// Handle the disk check
-pub fn handle_disk_check(rule_details: RuleConfig) {
-    log::info(format!("Running Rule: {}", rule_details.name));
+pub fn handle_disk_check(rule_details: &RuleConfig) -> Vec<CheckResult> {
     let disk_info = Disks::new_with_refreshed_list();
     let rule_name = &rule_details.name;
-    let mut failures: Vec<Vec<String>> = Vec::new();
+    let mut results: Vec<CheckResult> = Vec::new();
 
     // Loop through the rules
-    for rule in rule_details.rules {
+    for rule in &rule_details.rules {
         match get_disk_info(&rule["disk"], &disk_info) {
             Some(disk) => {
                 let disk_info = DiskSpace {
@@ -37,6 +35,13 @@ pub fn handle_disk_check(rule_details: RuleConfig) {
                 let human_available = disk_info.available / 1024.0 / 1024.0;
                 let human_total = disk_info.total / 1024.0 / 1024.0;
 
+                let severity = rule
+                    .get("severity")
+                    .and_then(|s| s.parse::<Severity>().ok())
+                    .unwrap_or_default();
+                let value = format!("{} MB free of {} MB", human_available.round(), human_total.round());
+                let threshold = format!("{} {}", rule["limit"], rule["option"]);
+
                 if check_space(&rule["option"], &disk_info, &rule["limit"]) {
                     let msg = vec!(
                         format!("Rule '{rule_name}' failed for mount point '{}'", rule["disk"]),
@@ -44,10 +49,32 @@ pub fn handle_disk_check(rule_details: RuleConfig) {
                         format!("Warning Limit: {} {}", rule["limit"], rule["option"])
                     );
 
-
-                    failures.push(msg);
+                    results.push(CheckResult {
+                        entry: rule["disk"].clone(),
+                        failed: true,
+                        severity,
+                        value: Some(value),
+                        threshold: Some(threshold),
+                        msg,
+                    });
                 } else {
-                    log::info(format!("Rule '{}' Passed for mount point '{}'", rule_name, rule["disk"]));
+                    log::info_with(
+                        format!("Rule '{}' Passed for mount point '{}'", rule_name, rule["disk"]),
+                        &log::Fields {
+                            rule: Some(rule_name),
+                            target: Some(&rule["disk"]),
+                            severity: None,
+                            value: Some(&value),
+                        },
+                    );
+                    results.push(CheckResult {
+                        entry: rule["disk"].clone(),
+                        failed: false,
+                        severity,
+                        value: Some(value),
+                        threshold: Some(threshold),
+                        msg: Vec::new(),
+                    });
                 }
             }
             None => {
@@ -56,55 +83,30 @@ pub fn handle_disk_check(rule_details: RuleConfig) {
         }
     }
 
-    if failures.len() > 0 {
-        handle_alerts(failures, rule_name, &rule_details.alerts);
-    }
-}
-
-/// Handle alerts if there are any failures
-fn handle_alerts(failure_msgs: Vec<Vec<String>>, rule_name: &String, alerts: &HashMap<String, Alert>) {
-    let mut html_formated_msgs: Vec<String> = Vec::new();
-
-    for failure_msg in failure_msgs {
-        log::fail(failure_msg.join(" - "));
-
-        // Formats all the failure messages for email
-        html_formated_msgs.push(failure_msg.join("<br />"));
-    }
-
-    if alerts.contains_key("email") {
-        let email = alerts.get("email").unwrap();
-        let contacts = &email.contacts;
-        alert(rule_name, html_formated_msgs.join("<br /><hr /><br />"), contacts);
-    }
+    results
 }
 
 /// Check the disk space
-fn check_space(option: &String, disk: &DiskSpace, limit: &String) -> bool {
+fn check_space(option: &str, disk: &DiskSpace, limit: &str) -> bool {
     match parse_limit(limit) {
         Some(l) => {
             // option can be free or used
             // free = it will check to see if the available free space is with in the limit
             // used = it will check to see if the used space is less then what is currently being used
-            match option.as_str() {
-                "free" => {
-                    return free_check(&l, disk);
-                }
-                "used" => {
-                    return used_check(&l, disk);
-                }
+            match option {
+                "free" => free_check(&l, disk),
+                "used" => used_check(&l, disk),
                 _ => {
                     log::fail(format!("Unknown disk check type '{}'", option));
+                    false
                 }
             }
-
-            return false;
         }
         None => {
             log::fail("Failed to parse rule for disk size check".to_string());
-            return false;
+            false
         }
-    };
+    }
 }
 
 /// Check the free space
@@ -120,14 +122,14 @@ fn free_check(limit: &Limit, disk: &DiskSpace) -> bool {
     let limit_type = size_conversion(&limit.limit_type);
     // 0 means no conversion option found, this means that the rule doesn't have
     // a size limit with a KB, MB, GB, or TB definition such as "200MB".
-    return if limit_type > 0 {
+    if limit_type > 0 {
         let convert_size = u64::pow(1024, limit_type) as f64;
         let min_ava = limit.amount * convert_size;
         min_ava > available
     } else {
         log::fail("Failed to parse rule for disk size check".to_string());
         false
-    };
+    }
 }
 
 /// Check the used space
@@ -146,7 +148,7 @@ fn used_check(limit: &Limit, disk: &DiskSpace) -> bool {
     let limit_type = size_conversion(&limit.limit_type);
     // 0 means no conversion option found, this means that the rule doesn't have
     // a size limit with a KB, MB, GB, or TB definition such as "200MB".
-    return if limit_type > 0 {
+    if limit_type > 0 {
         let convert_size = u64::pow(1024, limit_type) as f64;
         let used_max = limit.amount * convert_size;
         log::debug(format!("Total used: {}, Used max: {}", used_total, used_max));
@@ -154,7 +156,7 @@ fn used_check(limit: &Limit, disk: &DiskSpace) -> bool {
     } else {
         log::fail("Failed to parse rule for disk size check".to_string());
         false
-    };
+    }
 }
 
 /// Convert the size string to a number
@@ -170,7 +172,7 @@ fn size_conversion(size_string: &String) -> u32 {
         return size_conversion_map[size_string];
     }
 
-    return 0;
+    0
 }
 
 /// Get the disk info defined in the rule
@@ -191,7 +193,7 @@ fn get_disk_info<'a>(disk_mount: &'a String, disks: &'a Disks) -> Option<&'a Dis
 }
 
 /// Parse the limit string
-fn parse_limit(limit: &String) -> Option<Limit> {
+fn parse_limit(limit: &str) -> Option<Limit> {
     let rx = match Regex::new(r"^([\d\.]*)\s?(\D{1,2})?$") {
         Ok(re) => re,
         Err(e) => {
@@ -200,7 +202,7 @@ fn parse_limit(limit: &String) -> Option<Limit> {
         }
     };
 
-    match rx.captures(&limit) {
+    match rx.captures(limit) {
         Some(cap) => {
             if cap.get(2).is_some() {
                 let amount = cap[1].to_owned().parse::<f64>().unwrap();
@@ -209,11 +211,9 @@ fn parse_limit(limit: &String) -> Option<Limit> {
                 return Some(Limit { amount, limit_type });
             }
 
-            return None;
-        }
-        None => {
-            return None;
+            None
         }
+        None => None,
     }
 }
 
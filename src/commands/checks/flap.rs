@@ -0,0 +1,267 @@
+use super::CheckResult;
+use crate::alerts::Failure;
+use crate::rules::RuleConfig;
+use crate::state::{EntryState, State};
+use crate::{duration, log};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Flap suppression settings for a rule
+struct FlapSettings {
+    /// Number of failed checks in a row before alerting
+    failures: u32,
+    /// How long the check has to keep failing before alerting
+    hold: Duration,
+    /// Number of passed checks in a row before the entry counts as recovered
+    successes: u32,
+}
+
+impl FlapSettings {
+    fn from_rule(rule: &RuleConfig) -> FlapSettings {
+        let hold = match &rule.for_duration {
+            Some(d) => duration::parse(d).unwrap_or_else(|| {
+                log::error(format!("Rule '{}' has an invalid 'for' duration: {}", rule.name, d));
+                Duration::ZERO
+            }),
+            None => Duration::ZERO,
+        };
+
+        FlapSettings {
+            failures: rule.consecutive_failures.unwrap_or(1).max(1),
+            hold,
+            successes: rule.consecutive_successes.unwrap_or(1).max(1),
+        }
+    }
+}
+
+/// Alerts to send after processing the check results
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// Failures that should alert
+    pub failures: Vec<Failure>,
+    /// Entries that recovered after alerting
+    pub recovered: Vec<String>,
+    /// Why the failures aren't being alerted, such as a maintenance window
+    pub suppressed: Option<String>,
+}
+
+/// Update the state with the check results and return what should be alerted
+pub fn process_results(rule: &RuleConfig, results: &[CheckResult], state: &mut State) -> Outcome {
+    let settings = FlapSettings::from_rule(rule);
+    let now = Utc::now();
+    let mut outcome = Outcome::default();
+
+    for result in results {
+        let entry = state.entry(&rule.name, &result.entry);
+        let was_alerting = entry.alerting;
+
+        let alerting = update_entry(entry, result.failed, &settings, now);
+        if result.failed {
+            entry.severity = Some(result.severity);
+        }
+
+        if alerting {
+            let mut msg = result.msg.clone();
+            if let Some(since) = entry.failing_since {
+                msg.push(format!("Failing Since: {}", since.to_rfc2822()));
+            }
+            if let Some(ack) = entry.ack.take() {
+                if result.severity <= ack.severity {
+                    log::info(format!(
+                        "Rule '{}' failing for '{}' was acknowledged, not alerting",
+                        rule.name, result.entry
+                    ));
+                    entry.ack = Some(ack);
+                    continue;
+                }
+                msg.push(format!(
+                    "Severity rose from {} to {} after it was acknowledged",
+                    ack.severity, result.severity
+                ));
+            }
+            outcome.failures.push(Failure {
+                entry: result.entry.clone(),
+                severity: result.severity,
+                value: result.value.clone(),
+                threshold: result.threshold.clone(),
+                failing_since: entry.failing_since,
+                alerting_since: entry.alerting_since,
+                msg,
+            });
+        } else if result.failed {
+            log::info(format!(
+                "Rule '{}' failing for '{}' ({}/{} checks), alert suppressed",
+                rule.name, result.entry, entry.consecutive_failures, settings.failures
+            ));
+        } else if was_alerting && !entry.alerting {
+            log::info(format!("Rule '{}' recovered for '{}'", rule.name, result.entry));
+            outcome.recovered.push(result.entry.clone());
+        }
+    }
+
+    outcome
+}
+
+/// Apply a single check result to the entry state, returns true when the entry should alert
+fn update_entry(entry: &mut EntryState, failed: bool, settings: &FlapSettings, now: DateTime<Utc>) -> bool {
+    if failed {
+        entry.consecutive_successes = 0;
+        entry.consecutive_failures = entry.consecutive_failures.saturating_add(1);
+        let since = *entry.failing_since.get_or_insert(now);
+
+        let held = (now - since).to_std().unwrap_or(Duration::ZERO);
+        if entry.consecutive_failures >= settings.failures && held >= settings.hold {
+            entry.alerting = true;
+            entry.alerting_since.get_or_insert(now);
+        }
+
+        return entry.alerting;
+    }
+
+    entry.consecutive_failures = 0;
+    entry.consecutive_successes = entry.consecutive_successes.saturating_add(1);
+
+    // Stay in the alerting state until enough checks in a row have passed
+    if !entry.alerting || entry.consecutive_successes >= settings.successes {
+        entry.alerting = false;
+        entry.failing_since = None;
+        entry.alerting_since = None;
+        entry.severity = None;
+        entry.ack = None;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::Severity;
+
+    fn settings(failures: u32, hold: u64, successes: u32) -> FlapSettings {
+        FlapSettings {
+            failures,
+            hold: Duration::from_secs(hold),
+            successes,
+        }
+    }
+
+    #[test]
+    fn test_consecutive_failures() {
+        let s = settings(3, 0, 1);
+        let mut entry = EntryState::default();
+        let now = Utc::now();
+
+        assert!(!update_entry(&mut entry, true, &s, now));
+        assert!(!update_entry(&mut entry, true, &s, now));
+        assert!(update_entry(&mut entry, true, &s, now));
+        assert_eq!(entry.failing_since, Some(now));
+
+        // A pass resets the count
+        assert!(!update_entry(&mut entry, false, &s, now));
+        assert!(!entry.alerting);
+        assert!(entry.failing_since.is_none());
+        assert!(!update_entry(&mut entry, true, &s, now));
+    }
+
+    #[test]
+    fn test_hold_duration() {
+        let s = settings(1, 300, 1);
+        let mut entry = EntryState::default();
+        let start = Utc::now();
+
+        assert!(!update_entry(&mut entry, true, &s, start));
+        assert!(!update_entry(&mut entry, true, &s, start + chrono::Duration::minutes(4)));
+        let alerted = start + chrono::Duration::minutes(5);
+        assert!(update_entry(&mut entry, true, &s, alerted));
+        assert_eq!(entry.failing_since, Some(start));
+        assert_eq!(entry.alerting_since, Some(alerted));
+
+        // Still alerting from the first alert
+        assert!(update_entry(&mut entry, true, &s, alerted + chrono::Duration::minutes(5)));
+        assert_eq!(entry.alerting_since, Some(alerted));
+    }
+
+    #[test]
+    fn test_recovery_count() {
+        let s = settings(1, 0, 2);
+        let mut entry = EntryState::default();
+        let now = Utc::now();
+
+        assert!(update_entry(&mut entry, true, &s, now));
+
+        // One pass isn't enough to recover
+        assert!(!update_entry(&mut entry, false, &s, now));
+        assert!(entry.alerting);
+
+        // Failing again while recovering alerts straight away
+        assert!(update_entry(&mut entry, true, &s, now));
+
+        assert!(!update_entry(&mut entry, false, &s, now));
+        assert!(!update_entry(&mut entry, false, &s, now));
+        assert!(!entry.alerting);
+        assert!(entry.failing_since.is_none());
+        assert!(entry.alerting_since.is_none());
+    }
+
+    #[test]
+    fn test_process_results_recovered() {
+        let rule: RuleConfig =
+            serde_json::from_str(r#"{"name": "disks", "rule_type": "disk", "rules": [], "alerts": {}}"#).unwrap();
+        let result = |failed: bool| CheckResult {
+            entry: "/".to_string(),
+            failed,
+            severity: Default::default(),
+            value: None,
+            threshold: None,
+            msg: vec!["Disk / is low".to_string()],
+        };
+        let mut state = State::default();
+
+        let outcome = process_results(&rule, &[result(true)], &mut state);
+        assert_eq!(outcome.failures.len(), 1);
+        assert!(outcome.recovered.is_empty());
+
+        let outcome = process_results(&rule, &[result(false)], &mut state);
+        assert!(outcome.failures.is_empty());
+        assert_eq!(outcome.recovered, vec!["/".to_string()]);
+
+        // Only the change from alerting counts as a recovery
+        let outcome = process_results(&rule, &[result(false)], &mut state);
+        assert!(outcome.recovered.is_empty());
+    }
+
+    #[test]
+    fn test_acknowledged() {
+        let rule: RuleConfig =
+            serde_json::from_str(r#"{"name": "disks", "rule_type": "disk", "rules": [], "alerts": {}}"#).unwrap();
+        let result = |failed: bool, severity: Severity| CheckResult {
+            entry: "/".to_string(),
+            failed,
+            severity,
+            value: None,
+            threshold: None,
+            msg: Vec::new(),
+        };
+        let mut state = State::default();
+
+        process_results(&rule, &[result(true, Severity::Warning)], &mut state);
+        state.acknowledge("disks", &[], None, Utc::now()).unwrap();
+
+        let outcome = process_results(&rule, &[result(true, Severity::Warning)], &mut state);
+        assert!(outcome.failures.is_empty());
+
+        // Getting worse alerts again and drops the acknowledgement
+        let outcome = process_results(&rule, &[result(true, Severity::Critical)], &mut state);
+        assert_eq!(outcome.failures.len(), 1);
+        assert!(outcome.failures[0].msg[1].starts_with("Severity rose from warning to critical"));
+        assert!(state.entry("disks", "/").ack.is_none());
+
+        // Recovering clears it too
+        state.acknowledge("disks", &[], None, Utc::now()).unwrap();
+        process_results(&rule, &[result(false, Severity::Critical)], &mut state);
+        assert!(state.entry("disks", "/").ack.is_none());
+        let outcome = process_results(&rule, &[result(true, Severity::Critical)], &mut state);
+        assert_eq!(outcome.failures.len(), 1);
+    }
+}
@@ -1,18 +1,177 @@
-use crate::rules::RuleConfig;
+use crate::alerts::{self, Failure, Notification};
+use crate::rules::{RuleConfig, Severity};
+use crate::state::State;
+use crate::{config, enrich, log, remediation};
 use disk::handle_disk_check;
+use flap::Outcome;
+use serde::Serialize;
+use std::time::Instant;
 
 pub mod disk;
+pub mod flap;
+pub mod silence;
+
+/// Supported rule types
+pub const RULE_TYPES: &[&str] = &["disk"];
+
+/// Result of checking a single entry of a rule, such as a mount point
+#[derive(Serialize, Debug)]
+pub struct CheckResult {
+    pub entry: String,
+    pub failed: bool,
+    pub severity: Severity,
+    /// Measured value, such as the free space on a disk
+    pub value: Option<String>,
+    /// Limit the value was checked against
+    pub threshold: Option<String>,
+    pub msg: Vec<String>,
+}
+
+/// Run a rule check and handle any alerts
+pub fn exec_rule_check(rule: RuleConfig, state: &mut State) -> Result<Vec<CheckResult>, String> {
+    let results = run_check(&rule)?;
+    alerts::logging::log_results(&rule.name, &results);
+    let mut outcome = flap::process_results(&rule, &results, state);
+    silence::apply(&rule.name, &mut outcome, state);
+    if outcome.suppressed.is_none() {
+        remediate(&rule, &mut outcome.failures, None, || run_check(&rule));
+    }
+    handle_alerts(outcome, &rule);
+
+    Ok(results)
+}
+
+/// Send a test alert through every alert channel configured for the rule
+pub fn send_test_alert(rule: &RuleConfig) {
+    let failure = Failure {
+        entry: "test".to_string(),
+        severity: Severity::Info,
+        value: None,
+        threshold: None,
+        failing_since: None,
+        alerting_since: None,
+        msg: vec![
+            format!("Test alert for rule '{}'", rule.name),
+            "This is a test, no action is needed".to_string(),
+        ],
+    };
+
+    let outcome = Outcome {
+        failures: vec![failure],
+        recovered: Vec::new(),
+        suppressed: None,
+    };
+    handle_alerts(outcome, rule);
+}
 
 /// Check types
 /// - disk
 /// - cpu
 /// - memory
-pub fn exec_rule_check(rule: RuleConfig) {
+pub fn run_check(rule: &RuleConfig) -> Result<Vec<CheckResult>, String> {
+    log::info(format!("Running Rule: {}", rule.name));
+
     // determine rule
     match rule.rule_type.as_str() {
-        "disk" => handle_disk_check(rule),
-        "cpu" => todo!("cpu"),
-        _ => todo!("test"),
+        "disk" => Ok(handle_disk_check(rule)),
+        _ => Err(format!(
+            "Rule '{}' has an unsupported rule type '{}'",
+            rule.name, rule.rule_type
+        )),
+    }
+}
+
+/// Run the rule's remediation steps for failures that are about to alert, then check the rule again
+///
+/// What was done, and whether each failure cleared, is added to the failure messages. The steps stop
+/// at the deadline, and `recheck` is expected to give up at it too.
+pub fn remediate(
+    rule: &RuleConfig,
+    failures: &mut [Failure],
+    deadline: Option<Instant>,
+    recheck: impl FnOnce() -> Result<Vec<CheckResult>, String>,
+) {
+    if rule.remediation.is_empty() || failures.is_empty() {
+        return;
+    }
+
+    let dry_run = config::overrides().dry_run;
+    let steps = remediation::run_all(&rule.name, &rule.remediation, dry_run, deadline);
+    let lines: Vec<String> = steps
+        .iter()
+        .map(|step| {
+            if step.ok {
+                format!("Remediation: {}", step.description)
+            } else {
+                format!("Remediation failed: {}", step.description)
+            }
+        })
+        .collect();
+
+    // Nothing was changed in a dry run so there's no point checking again
+    let recheck = if dry_run {
+        None
+    } else {
+        log::info(format!("Checking rule '{}' again after remediation", rule.name));
+        match recheck() {
+            Ok(results) => {
+                alerts::logging::log_results(&rule.name, &results);
+                Some(results)
+            }
+            Err(e) => {
+                log::error(e);
+                None
+            }
+        }
+    };
+
+    for failure in failures {
+        failure.msg.extend(lines.iter().cloned());
+
+        let Some(result) = recheck.iter().flatten().find(|r| r.entry == failure.entry) else {
+            continue;
+        };
+        let value = result.value.as_deref().unwrap_or("no value");
+        if result.failed {
+            failure.msg.push(format!("Still failing after remediation: {}", value));
+        } else {
+            failure.msg.push(format!("Cleared after remediation: {}", value));
+        }
     }
-    // process rule logic
+}
+
+/// Send alerts for any failures and resolve incidents for entries that recovered
+pub fn handle_alerts(outcome: Outcome, rule: &RuleConfig) {
+    let rule_name = &rule.name;
+    if !outcome.recovered.is_empty() {
+        alerts::resolve(rule_name, &outcome.recovered, &rule.alerts);
+    }
+
+    if outcome.failures.is_empty() {
+        return;
+    }
+
+    for failure in &outcome.failures {
+        let severity = failure.severity.to_string();
+        log::fail_with(
+            failure.msg.join(" - "),
+            &log::Fields {
+                rule: Some(rule_name),
+                target: Some(&failure.entry),
+                severity: Some(&severity),
+                value: failure.value.as_deref(),
+            },
+        );
+    }
+
+    if let Some(reason) = &outcome.suppressed {
+        log::info(format!("Rule '{}' is {}, not sending alerts", rule_name, reason));
+        alerts::logging::log_suppressed(None, &Notification::new(rule_name, outcome.failures), reason);
+        return;
+    }
+
+    let mut notification = Notification::new(rule_name, outcome.failures);
+    notification.diagnostics = enrich::gather(rule, &notification.failures);
+
+    alerts::notify(&notification, &rule.alerts);
 }
@@ -0,0 +1,175 @@
+use super::flap::Outcome;
+use crate::config::{self, MaintenanceConf};
+use crate::state::{Silence, State};
+use crate::{alerts, log};
+use chrono::{DateTime, Utc};
+
+/// Hold back the rule's alerts during a maintenance window or silence, and note how many alerts
+/// were held back when either ends
+pub fn apply(rule: &str, outcome: &mut Outcome, state: &mut State) {
+    let config = config::get();
+    apply_at(rule, outcome, state, &config.maintenance, &alerts::host_name(), Utc::now());
+}
+
+fn apply_at(
+    rule: &str,
+    outcome: &mut Outcome,
+    state: &mut State,
+    windows: &[MaintenanceConf],
+    host: &str,
+    now: DateTime<Utc>,
+) {
+    if let Some(window) = maintenance(windows, rule, host, now) {
+        let mut reason = format!("in maintenance until {}", window.end.to_rfc2822());
+        if let Some(r) = &window.reason {
+            reason.push_str(&format!(": {}", r));
+        }
+        outcome.suppressed = Some(reason);
+
+        let held = state.rules.entry(rule.to_string()).or_default().maintenance.get_or_insert(Silence {
+            until: window.end.with_timezone(&Utc),
+            reason: window.reason.clone(),
+            created: now,
+            suppressed: 0,
+        });
+        if !outcome.failures.is_empty() {
+            held.suppressed += 1;
+        }
+        return;
+    }
+
+    let Some(rule_state) = state.rules.get_mut(rule) else {
+        return;
+    };
+
+    if let Some(held) = rule_state.maintenance.take() {
+        log::info(format!("Maintenance for rule '{}' has ended", rule));
+        held_back(
+            outcome,
+            format!("Maintenance ended, {} alert(s) were held back", held.suppressed),
+        );
+    }
+
+    let Some(silence) = rule_state.silence.as_mut() else {
+        return;
+    };
+
+    if silence.until > now {
+        if !outcome.failures.is_empty() {
+            silence.suppressed += 1;
+        }
+        let mut reason = format!("silenced until {}", silence.until.to_rfc2822());
+        if let Some(r) = &silence.reason {
+            reason.push_str(&format!(": {}", r));
+        }
+        outcome.suppressed = Some(reason);
+        return;
+    }
+
+    let Some(silence) = rule_state.silence.take() else {
+        return;
+    };
+    log::info(format!("Silence for rule '{}' has ended", rule));
+
+    held_back(
+        outcome,
+        format!(
+            "Silence ended at {}, {} alert(s) were held back",
+            silence.until.to_rfc2822(),
+            silence.suppressed
+        ),
+    );
+}
+
+/// Add the summary to the failures, the rule is still failing so the alert says what was missed
+fn held_back(outcome: &mut Outcome, summary: String) {
+    for failure in &mut outcome.failures {
+        failure.msg.push(summary.clone());
+    }
+}
+
+/// The maintenance window covering a rule on a host at a time
+fn maintenance<'a>(
+    windows: &'a [MaintenanceConf],
+    rule: &str,
+    host: &str,
+    now: DateTime<Utc>,
+) -> Option<&'a MaintenanceConf> {
+    windows.iter().find(|w| {
+        w.start <= now
+            && now < w.end
+            && (w.hosts.is_empty() || w.hosts.iter().any(|h| h.eq_ignore_ascii_case(host)))
+            && (w.rules.is_empty() || w.rules.iter().any(|r| r == rule))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alerts::test_notification;
+    use chrono::Duration;
+    use serde_json::json;
+
+    fn failing() -> Outcome {
+        Outcome {
+            failures: test_notification(&[("/", Default::default())]).failures,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_maintenance() {
+        let windows: Vec<MaintenanceConf> = serde_json::from_value(json!([{
+            "start": "2024-06-03T22:00:00+02:00",
+            "end": "2024-06-04T02:00:00+02:00",
+            "hosts": ["db1"],
+            "reason": "disk swap",
+        }]))
+        .unwrap();
+        let during: DateTime<Utc> = "2024-06-03T21:00:00Z".parse().unwrap();
+
+        assert!(maintenance(&windows, "disks", "DB1", during).is_some());
+        assert!(maintenance(&windows, "disks", "web1", during).is_none());
+        assert!(maintenance(&windows, "disks", "db1", during + Duration::hours(3)).is_none());
+
+        let mut state = State::default();
+        let mut outcome = failing();
+        apply_at("disks", &mut outcome, &mut state, &windows, "db1", during);
+        assert!(outcome.suppressed.unwrap().ends_with(": disk swap"));
+        apply_at("disks", &mut failing(), &mut state, &windows, "db1", during);
+        assert_eq!(state.rules["disks"].maintenance.as_ref().unwrap().suppressed, 2);
+
+        // The first alert after the window says how many were held back
+        let mut outcome = failing();
+        apply_at("disks", &mut outcome, &mut state, &windows, "db1", during + Duration::hours(3));
+        assert!(outcome.suppressed.is_none());
+        assert_eq!(outcome.failures[0].msg[1], "Maintenance ended, 2 alert(s) were held back");
+        assert!(state.rules["disks"].maintenance.is_none());
+    }
+
+    #[test]
+    fn test_silence() {
+        let now = Utc::now();
+        let mut state = State::default();
+        state.rules.entry("disks".to_string()).or_default().silence = Some(Silence {
+            until: now + Duration::hours(2),
+            reason: Some("moving data".to_string()),
+            created: now,
+            suppressed: 0,
+        });
+
+        let mut outcome = failing();
+        apply_at("disks", &mut outcome, &mut state, &[], "db1", now);
+        assert!(outcome.suppressed.is_some());
+        let mut outcome = Outcome::default();
+        apply_at("disks", &mut outcome, &mut state, &[], "db1", now);
+        assert_eq!(state.rules["disks"].silence.as_ref().unwrap().suppressed, 1);
+
+        // Once it ends the next alert says how many were held back
+        let mut outcome = failing();
+        apply_at("disks", &mut outcome, &mut state, &[], "db1", now + Duration::hours(3));
+        assert!(outcome.suppressed.is_none());
+        assert!(outcome.failures[0].msg[1].ends_with("1 alert(s) were held back"));
+        assert!(state.rules["disks"].silence.is_none());
+    }
+}
@@ -1,14 +1,31 @@
+use serde::Serialize;
 use sysinfo::Disks;
 
-pub fn display_disks() {
-    let display_size = u64::pow(1024, 3);
-    let disks = Disks::new_with_refreshed_list();
-    for disk in &disks {
-        let usage = disk.usage();
-        let used = usage.total_written_bytes / display_size;
-        let total = disk.total_space() / display_size;
-        let free = disk.available_space() / display_size;
+/// Details shown for each disk
+#[derive(Serialize, Debug)]
+struct DiskDetails {
+    mount_point: String,
+    name: String,
+    file_system: String,
+    kind: String,
+    removable: bool,
+    total_gb: u64,
+    used_gb: u64,
+    free_gb: u64,
+}
+
+pub fn display_disks(json: bool) {
+    let details = disk_details();
+
+    if json {
+        match serde_json::to_string_pretty(&details) {
+            Ok(out) => println!("{}", out),
+            Err(e) => crate::log::error(format!("Failed to format disk details: {}", e)),
+        }
+        return;
+    }
 
+    for disk in details {
         print!(
             "Mount Point: {:?}
 Name: {:?}
@@ -20,14 +37,38 @@ Size:
     - Used: {} GB
     - Free: {} GB
 -------------------------------\n",
-            disk.mount_point(),
-            disk.name(),
-            disk.file_system(),
-            disk.kind(),
-            disk.is_removable(),
-            total,
-            used,
-            free
+            disk.mount_point,
+            disk.name,
+            disk.file_system,
+            disk.kind,
+            disk.removable,
+            disk.total_gb,
+            disk.used_gb,
+            disk.free_gb
         );
     }
 }
+
+fn disk_details() -> Vec<DiskDetails> {
+    let display_size = u64::pow(1024, 3);
+    let disks = Disks::new_with_refreshed_list();
+
+    disks
+        .iter()
+        .map(|disk| {
+            let total = disk.total_space();
+            let free = disk.available_space();
+
+            DiskDetails {
+                mount_point: disk.mount_point().display().to_string(),
+                name: disk.name().to_string_lossy().to_string(),
+                file_system: disk.file_system().to_string_lossy().to_string(),
+                kind: disk.kind().to_string(),
+                removable: disk.is_removable(),
+                total_gb: total / display_size,
+                used_gb: total.saturating_sub(free) / display_size,
+                free_gb: free / display_size,
+            }
+        })
+        .collect()
+}
@@ -1,2 +1,4 @@
+pub mod ack;
 pub mod checks;
 pub mod disk;
+pub mod silence;
@@ -0,0 +1,43 @@
+use crate::rules::get_single_rule;
+use crate::state::{self, Silence};
+use crate::{duration, log};
+use chrono::Utc;
+
+/// Hold back a rule's alerts for a while, or clear the silence with `clear`
+///
+/// The silence is kept in the state file so a running daemon picks it up on the rule's next check.
+pub fn silence(rule: &str, length: Option<&str>, reason: Option<String>, clear: bool) -> Result<(), String> {
+    let rule = get_single_rule(rule.to_string())?.name;
+    let _lock = state::lock();
+    let mut state = state::load();
+    let rule_state = state.rules.entry(rule.clone()).or_default();
+
+    if clear {
+        match rule_state.silence.take() {
+            Some(s) => log::info(format!(
+                "Cleared the silence for rule '{}', {} alert(s) were held back",
+                rule, s.suppressed
+            )),
+            None => log::info(format!("Rule '{}' isn't silenced", rule)),
+        }
+    } else {
+        let length = length.ok_or("--for is needed to silence a rule".to_string())?;
+        let length = duration::parse(length)
+            .and_then(|d| chrono::Duration::from_std(d).ok())
+            .ok_or(format!("Invalid duration: {}", length))?;
+        let now = Utc::now();
+        let until = now + length;
+
+        rule_state.silence = Some(Silence {
+            until,
+            reason,
+            created: now,
+            suppressed: 0,
+        });
+        log::info(format!("Silenced rule '{}' until {}", rule, until.to_rfc2822()));
+    }
+
+    state::save(&state);
+
+    Ok(())
+}
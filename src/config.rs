@@ -1,18 +1,114 @@
-use crate::log;
+use crate::alerts::contacts;
+use crate::{duration, format, log};
+use crate::secret::{self, Secret};
+use crate::sink::SyslogConf;
+use crate::template::Template;
+use chrono::{DateTime, FixedOffset, NaiveTime, Weekday};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap, env::current_dir, fs, path::PathBuf, process::exit, sync::OnceLock,
+    collections::HashMap,
+    env::{self, current_dir},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+    sync::{Arc, OnceLock, PoisonError, RwLock},
 };
 
-pub static DEBUG: OnceLock<bool> = OnceLock::new();
-pub static RULES_PATH: OnceLock<PathBuf> = OnceLock::new();
-pub static CONFIG: OnceLock<Config> = OnceLock::new();
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+pub static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+static CONFIG: RwLock<Option<Arc<Config>>> = RwLock::new(None);
+
+/// Settings given on the command line, these take priority over the config file
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub rules_dir: Option<PathBuf>,
+    pub log_level: Option<log::Level>,
+    pub log_format: Option<log::LogFormat>,
+    pub dry_run: bool,
+    /// Send log messages to stderr so stdout only has the command output
+    pub log_to_stderr: bool,
+}
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Config {
     debug: Option<bool>,
     pub rules: RulesConf,
     pub alerts: AlertsConf,
+    pub state: Option<StateConf>,
+    #[serde(default)]
+    pub daemon: DaemonConf,
+    #[serde(default)]
+    pub log: LogConf,
+    #[serde(default)]
+    pub contacts: ContactsConf,
+    /// Recurring times when some contacts or channels aren't sent alerts
+    #[serde(default)]
+    pub quiet_hours: Vec<QuietHoursConf>,
+    /// Planned maintenance when alerts are held back
+    #[serde(default)]
+    pub maintenance: Vec<MaintenanceConf>,
+}
+
+/// A daily period, in local time, when alerts aren't sent to some contacts or channels
+///
+/// With no contacts or channels set every alert is held back.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct QuietHoursConf {
+    /// Time of day the quiet hours start, e.g. "22:00"
+    pub start: NaiveTime,
+    /// Time of day they end, before `start` means the next day
+    pub end: NaiveTime,
+    /// Days the quiet hours start on, every day if empty
+    #[serde(default)]
+    pub days: Vec<Weekday>,
+    /// Contacts or groups not sent alerts, the rest of the alert's contacts still are
+    #[serde(default)]
+    pub contacts: Vec<String>,
+    /// Alert names or types the quiet hours apply to, all of them if empty
+    #[serde(default)]
+    pub channels: Vec<String>,
+}
+
+/// A one-off window when rules aren't alerted
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MaintenanceConf {
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    /// Host names the window applies to, every host if empty
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Rules the window applies to, every rule if empty
+    #[serde(default)]
+    pub rules: Vec<String>,
+    pub reason: Option<String>,
+}
+
+/// Named contacts that rules can refer to instead of listing everyone
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ContactsConf {
+    /// Lists of contacts, used in rules as `group:<name>`
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<String>>,
+    /// Rotations of who is on call, used in rules as `oncall:<name>`
+    #[serde(default)]
+    pub oncall: HashMap<String, OnCallConf>,
+}
+
+/// Members take turns being on call, each for one shift
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OnCallConf {
+    /// Contacts or groups in the order they go on call
+    pub members: Vec<String>,
+    /// When the first member's shift starts, e.g. "2024-06-03T09:00:00+01:00"
+    pub start: DateTime<FixedOffset>,
+    /// Length of each shift, defaults to a week
+    #[serde(default = "default_shift")]
+    pub shift: String,
+}
+
+fn default_shift() -> String {
+    "1w".to_string()
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -20,82 +116,627 @@ pub struct RulesConf {
     pub location: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StateConf {
+    pub location: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DaemonConf {
+    /// Reload the config and rules when the files change
+    #[serde(default)]
+    pub watch: bool,
+    /// Address of the local HTTP endpoint for acknowledging alerts, e.g. "127.0.0.1:9119"
+    pub listen: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct LogConf {
+    /// Lowest level of message that is logged, defaults to info
+    pub level: Option<log::Level>,
+    /// Console log format, `text` or `json`
+    pub format: Option<log::LogFormat>,
+    /// Where log messages are written
+    #[serde(default)]
+    pub output: LogOutput,
+    /// Syslog settings, also used by syslog alerts
+    #[serde(default)]
+    pub syslog: SyslogConf,
+}
+
+/// Where log messages are written
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogOutput {
+    /// stdout, or stderr when the command output goes to stdout
+    #[default]
+    Console,
+    Syslog,
+    Journald,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AlertsConf {
     pub email: AlertEmailConf,
     pub logging: AlertLoggingConf,
+    pub ntfy: Option<AlertNtfyConf>,
+    pub gotify: Option<AlertGotifyConf>,
+    pub pushover: Option<AlertPushoverConf>,
+    pub telegram: Option<AlertTelegramConf>,
+    pub matrix: Option<AlertMatrixConf>,
+    /// Most alerts each channel can send, keyed by alert name or type
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimitConf>,
+    /// Send one grouped alert per channel instead of one per rule
+    pub digest: Option<DigestConf>,
+}
+
+/// Most alerts a channel can send in a period, across every rule
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RateLimitConf {
+    pub max: u32,
+    /// Length of the period, e.g. "1h"
+    pub per: String,
+}
+
+/// Failures from every rule are collected and sent together
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DigestConf {
+    /// How long the daemon collects failures before sending them, e.g. "5m"
+    #[serde(default = "default_digest_window")]
+    pub window: String,
+}
+
+fn default_digest_window() -> String {
+    "5m".to_string()
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AlertEmailConf {
+    #[serde(default)]
+    pub transport: EmailTransport,
+    #[serde(default)]
     pub smtp: String,
-    pub user: String,
-    pub password: String,
+    /// SMTP port, defaults to the standard port for the TLS mode
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: TlsMode,
+    /// SMTP username, no authentication is used if this isn't set
+    pub user: Option<String>,
+    pub password: Option<Secret>,
+    /// File holding the password, used instead of `password`
+    pub password_file: Option<String>,
+    /// SMTP connection timeout, e.g. "10s"
+    pub timeout: Option<String>,
+    /// Command used by the sendmail transport, defaults to `sendmail`
+    pub sendmail_command: Option<String>,
     pub from_address: String,
+    /// Custom templates for the alert email
+    #[serde(default)]
+    pub templates: EmailTemplates,
+}
+
+/// Email templates, anything not set uses the built in template
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct EmailTemplates {
+    pub subject: Option<String>,
+    pub text: Option<String>,
+    /// File holding the plain text template, used instead of `text`
+    pub text_file: Option<String>,
+    pub html: Option<String>,
+    /// File holding the HTML template, used instead of `html`
+    pub html_file: Option<String>,
+}
+
+impl EmailTemplates {
+    /// Load the template files and check the templates parse
+    fn resolve(&mut self, config_dir: &Path) -> Result<(), String> {
+        for (name, inline, file) in [
+            ("text", &mut self.text, &self.text_file),
+            ("html", &mut self.html, &self.html_file),
+        ] {
+            if let Some(path) = file {
+                if inline.is_some() {
+                    return Err(format!("Only one of alerts.email.templates.{} and {}_file can be set", name, name));
+                }
+                let path = config_dir.join(path);
+                let data = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read email template {}: {}", path.display(), e))?;
+                *inline = Some(data);
+            }
+        }
+
+        for (name, template) in [("subject", &self.subject), ("text", &self.text), ("html", &self.html)] {
+            if let Some(t) = template {
+                Template::parse(t).map_err(|e| format!("Invalid alerts.email.templates.{}: {}", name, e))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How email alerts are delivered
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmailTransport {
+    #[default]
+    Smtp,
+    /// Hand the email to the local MTA
+    Sendmail,
+}
+
+/// SMTP connection security
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TlsMode {
+    /// Plain text connection, usually port 25
+    None,
+    /// Upgrade the connection with STARTTLS, usually port 587
+    Starttls,
+    /// TLS from the start of the connection, usually port 465
+    #[default]
+    Implicit,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AlertNtfyConf {
+    /// ntfy server, defaults to https://ntfy.sh
+    #[serde(default = "default_ntfy_server")]
+    pub server: String,
+    /// Topic used when a rule doesn't set one
+    pub topic: Option<String>,
+    /// Access token for protected topics
+    pub token: Option<Secret>,
+    /// File holding the token, used instead of `token`
+    pub token_file: Option<String>,
+}
+
+fn default_ntfy_server() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AlertGotifyConf {
+    pub server: String,
+    /// Application token
+    pub token: Option<Secret>,
+    /// File holding the token, used instead of `token`
+    pub token_file: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AlertPushoverConf {
+    /// Application API token
+    pub token: Option<Secret>,
+    /// File holding the token, used instead of `token`
+    pub token_file: Option<String>,
+    /// User or group key used when a rule doesn't list any contacts
+    pub user: Option<String>,
+    /// Pushover API endpoint, only needs changing for testing
+    #[serde(default = "default_pushover_url")]
+    pub api_url: String,
+}
+
+fn default_pushover_url() -> String {
+    "https://api.pushover.net/1/messages.json".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AlertTelegramConf {
+    /// Bot token from BotFather
+    pub token: Option<Secret>,
+    /// File holding the token, used instead of `token`
+    pub token_file: Option<String>,
+    /// Telegram Bot API endpoint, only needs changing for a local Bot API server
+    #[serde(default = "default_telegram_url")]
+    pub api_url: String,
+}
+
+fn default_telegram_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AlertMatrixConf {
+    /// Homeserver URL, e.g. https://matrix.example.com
+    pub homeserver: String,
+    /// Access token of the bot account
+    pub access_token: Option<Secret>,
+    /// File holding the access token, used instead of `access_token`
+    pub access_token_file: Option<String>,
+}
+
+/// Log file that every check result and alert is written to
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AlertLoggingConf {
     pub location: String,
     pub file: String,
-    pub rotation: HashMap<String, String>,
+    #[serde(default)]
+    pub rotation: RotationConf,
 }
 
-pub fn load_config() {
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RotationConf {
+    #[serde(default)]
+    pub when: RotateWhen,
+    /// Size the file can grow to when rotating by size, e.g. "10MB", a plain number is in MB
+    #[serde(default = "default_rotation_limit")]
+    pub limit: String,
+    /// Number of old files to keep
+    #[serde(default = "default_rotation_keep")]
+    pub keep: u32,
+    /// Compress old files with gzip
+    #[serde(default)]
+    pub compress: bool,
+}
+
+impl Default for RotationConf {
+    fn default() -> RotationConf {
+        RotationConf {
+            when: RotateWhen::default(),
+            limit: default_rotation_limit(),
+            keep: default_rotation_keep(),
+            compress: false,
+        }
+    }
+}
+
+impl RotationConf {
+    /// The size limit in bytes
+    pub fn limit_bytes(&self) -> Result<u64, String> {
+        let limit = self.limit.trim().to_uppercase();
+        let split = limit.find(|c: char| !c.is_ascii_digit()).unwrap_or(limit.len());
+        let (number, unit) = limit.split_at(split);
+
+        let multiplier: u64 = match unit.trim() {
+            "B" => 1,
+            "KB" | "K" => 1024,
+            "" | "MB" | "M" => 1024 * 1024,
+            "GB" | "G" => 1024 * 1024 * 1024,
+            _ => return Err(format!("Invalid alerts.logging.rotation.limit: {}", self.limit)),
+        };
+
+        match number.parse::<u64>() {
+            Ok(n) if n > 0 => Ok(n * multiplier),
+            _ => Err(format!("Invalid alerts.logging.rotation.limit: {}", self.limit)),
+        }
+    }
+}
+
+fn default_rotation_limit() -> String {
+    "100MB".to_string()
+}
+
+fn default_rotation_keep() -> u32 {
+    5
+}
+
+/// When the log file is rotated
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotateWhen {
+    #[default]
+    Size,
+    Daily,
+    Weekly,
+    Never,
+}
+
+impl AlertLoggingConf {
+    /// Full path to the log file
+    pub fn path(&self) -> PathBuf {
+        Path::new(&self.location).join(&self.file)
+    }
+}
+
+impl Config {
+    /// Full path to the rules directory
+    pub fn rules_path(&self) -> PathBuf {
+        PathBuf::from(&self.rules.location)
+    }
+
+    /// Resolve the secret fields from the environment and files
+    ///
+    /// Returns true if any secret is written inline in the config file.
+    fn resolve_secrets(&mut self, config_dir: &Path) -> Result<bool, String> {
+        let alerts = &mut self.alerts;
+        let email = &mut alerts.email;
+        let mut secrets = vec![secret::resolve(
+            "alerts.email.password",
+            &mut email.password,
+            &email.password_file,
+            config_dir,
+        )?];
+
+        if let Some(ntfy) = alerts.ntfy.as_mut() {
+            secrets.push(secret::resolve("alerts.ntfy.token", &mut ntfy.token, &ntfy.token_file, config_dir)?);
+        }
+        if let Some(gotify) = alerts.gotify.as_mut() {
+            secrets.push(secret::resolve("alerts.gotify.token", &mut gotify.token, &gotify.token_file, config_dir)?);
+        }
+        if let Some(pushover) = alerts.pushover.as_mut() {
+            secrets.push(secret::resolve(
+                "alerts.pushover.token",
+                &mut pushover.token,
+                &pushover.token_file,
+                config_dir,
+            )?);
+        }
+        if let Some(telegram) = alerts.telegram.as_mut() {
+            secrets.push(secret::resolve(
+                "alerts.telegram.token",
+                &mut telegram.token,
+                &telegram.token_file,
+                config_dir,
+            )?);
+        }
+        if let Some(matrix) = alerts.matrix.as_mut() {
+            secrets.push(secret::resolve(
+                "alerts.matrix.access_token",
+                &mut matrix.access_token,
+                &matrix.access_token_file,
+                config_dir,
+            )?);
+        }
+
+        Ok(secrets.contains(&true))
+    }
+
+    /// Full path to the state file
+    pub fn state_path(&self) -> PathBuf {
+        match &self.state {
+            Some(s) => PathBuf::from(&s.location),
+            None => PathBuf::from("state.json"),
+        }
+    }
+}
+
+/// Get the command line overrides
+pub fn overrides() -> &'static Overrides {
+    OVERRIDES.get_or_init(Overrides::default)
+}
+
+/// Get the currently loaded config
+pub fn get() -> Arc<Config> {
+    CONFIG
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .expect("Config hasn't been loaded")
+}
+
+/// Get the currently loaded config, if it has been loaded yet
+pub fn try_get() -> Option<Arc<Config>> {
+    CONFIG.read().unwrap_or_else(PoisonError::into_inner).clone()
+}
+
+/// Replace the currently loaded config
+pub fn set(cnf: Config) {
+    apply_log_settings(Some(&cnf));
+
+    *CONFIG.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(cnf));
+}
+
+/// Set the log level and format
+///
+/// The command line wins over the `EEKWATCH_LOG_LEVEL` and `EEKWATCH_LOG_FORMAT` environment
+/// variables, which win over the config file.
+pub fn apply_log_settings(cnf: Option<&Config>) {
+    let debug = cnf.is_some_and(|c| c.debug.unwrap_or(false));
+    let level = overrides()
+        .log_level
+        .or_else(log::env_level)
+        .or(cnf.and_then(|c| c.log.level))
+        .unwrap_or(if debug { log::Level::Debug } else { log::Level::Info });
+    log::set_level(level);
+
+    let format = overrides()
+        .log_format
+        .or_else(log::env_format)
+        .or(cnf.and_then(|c| c.log.format))
+        .unwrap_or_default();
+    log::set_format(format);
+}
+
+/// Get the path of the config file to use
+///
+/// The `--config` flag is used first, then the `EEKWATCH_CONFIG` environment variable,
+/// then the first config file found in the search path.
+pub fn config_path() -> Result<&'static PathBuf, String> {
+    if let Some(path) = CONFIG_PATH.get() {
+        return Ok(path);
+    }
+
+    let path = find_config()?;
+
+    Ok(CONFIG_PATH.get_or_init(|| path))
+}
+
+fn find_config() -> Result<PathBuf, String> {
+    let cwd = current_dir().map_err(|e| format!("Failed to get the current directory: {}", e))?;
+
+    if let Some(path) = &overrides().config {
+        return Ok(build_path(path.display().to_string(), &cwd));
+    }
+
+    if let Some(path) = env::var_os("EEKWATCH_CONFIG").filter(|p| !p.is_empty()) {
+        return Ok(build_path(PathBuf::from(path).display().to_string(), &cwd));
+    }
+
+    let paths = search_paths(
+        &cwd,
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from),
+        env::var_os("HOME").map(PathBuf::from),
+    );
+
+    match paths.iter().find(|p| p.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(format!(
+            "No config file found, searched: {}",
+            paths
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// Places to look for the config file, in order
+fn search_paths(cwd: &Path, xdg_config_home: Option<PathBuf>, home: Option<PathBuf>) -> Vec<PathBuf> {
     // Check if the dev config file exists, fi so use it
-    let dev = fs::exists("config-dev.json").unwrap_or(false);
-    let config_file = if dev {
-        "config-dev.json"
-    } else {
-        "config.json"
-    };
+    let mut files = vec![cwd.join("config-dev"), cwd.join("config")];
+
+    let xdg = xdg_config_home
+        .filter(|p| p.has_root())
+        .or_else(|| home.map(|h| h.join(".config")));
+    if let Some(xdg) = xdg {
+        files.push(xdg.join("eekwatch").join("config"));
+    }
+
+    files.push(PathBuf::from("/etc/eekwatch/config"));
+
+    // Each location can use any of the supported formats
+    files
+        .iter()
+        .flat_map(|f| format::EXTENSIONS.iter().map(|ext| f.with_extension(ext)))
+        .collect()
+}
 
-    // Open the config file
-    let file = match fs::File::open(config_file) {
-        Ok(f) => f,
+pub fn load_config() {
+    let cnf = match config_path().and_then(|p| read_config(p)) {
+        Ok(c) => c,
         Err(e) => {
-            log::error(format!("Failed to open config file: {}", e));
+            log::error(e);
             exit(1);
         }
     };
 
-    // Parse the config file
-    let reader = std::io::BufReader::new(file);
-    let mut cnf: Config = match serde_json::from_reader(reader) {
+    // Load the config into memory for reference in other parts of the application
+    set(cnf);
+
+    let cnf = get();
+    log::debug(format!("Config file: {:?}", config_path()));
+    log::debug(format!("Config: {:?}", cnf));
+    log::debug(format!("Rules path: {:?}", cnf.rules_path()));
+    log::debug(format!("State file path: {:?}", cnf.state_path()));
+}
+
+/// Read and parse a config file
+///
+/// Relative paths in the config are resolved from the directory holding the config file.
+pub fn read_config(config_path: &Path) -> Result<Config, String> {
+    // Parse the config file, the format is based on the file extension
+    let mut cnf: Config = match format::read_file(config_path) {
         Ok(c) => c,
-        Err(e) => {
-            log::error(format!("Failed to parse config file: {}", e));
-            exit(1);
-        }
+        Err(e) => return Err(format!("Failed to load config file: {}", e)),
     };
 
-    let path = build_rules_path(cnf.rules.location);
-    let debug = cnf.debug.unwrap_or(false);
-    cnf.rules.location = path.display().to_string();
+    let config_dir = config_path.parent().unwrap_or(Path::new("/"));
 
-    // Load the config into memory for reference in other parts of the application
-    CONFIG.get_or_init(|| cnf);
+    cnf.alerts.email.templates.resolve(config_dir)?;
+    cnf.alerts.logging.rotation.limit_bytes()?;
+    contacts::validate_conf(&cnf.contacts)?;
+    for (name, limit) in &cnf.alerts.rate_limits {
+        duration::parse(&limit.per)
+            .filter(|p| !p.is_zero())
+            .ok_or(format!("Invalid rate limit period for '{}': {}", name, limit.per))?;
+    }
+    if let Some(digest) = &cnf.alerts.digest {
+        duration::parse(&digest.window).ok_or(format!("Invalid digest window: {}", digest.window))?;
+    }
+    for quiet in &cnf.quiet_hours {
+        contacts::expand(&cnf.contacts, &quiet.contacts, chrono::Utc::now())
+            .map_err(|e| format!("Invalid quiet hours contacts: {}", e))?;
+    }
+    for window in &cnf.maintenance {
+        if window.end <= window.start {
+            return Err(format!("Maintenance window starting {} has to end after it starts", window.start));
+        }
+    }
+
+    if cnf.resolve_secrets(config_dir)? && secret::is_world_readable(config_path) {
+        return Err(format!(
+            "Config file {} is readable by everyone and has secrets in it, \
+             restrict its permissions (chmod 600) or move the secrets to environment variables or *_file settings",
+            config_path.display()
+        ));
+    }
+
+    cnf.rules.location = match &overrides().rules_dir {
+        // The command line flag is relative to where it was run from
+        Some(rules_dir) => {
+            let cwd = current_dir().map_err(|e| format!("Failed to get the current directory: {}", e))?;
+            build_path(rules_dir.display().to_string(), &cwd)
+        }
+        None => build_path(cnf.rules.location, config_dir),
+    }
+    .display()
+    .to_string();
 
-    // Set the debug global flag
-    DEBUG.get_or_init(|| debug);
+    let logging = &mut cnf.alerts.logging;
+    logging.location = build_path(logging.location.clone(), config_dir).display().to_string();
 
-    log::debug("DEBUG flag enabled in config".to_string());
-    log::debug(format!("Rules path: {:?}", path));
+    let state_location = match cnf.state.take() {
+        Some(s) => s.location,
+        None => "state.json".to_string(),
+    };
+    cnf.state = Some(StateConf {
+        location: build_path(state_location, config_dir).display().to_string(),
+    });
 
-    let p = RULES_PATH.get_or_init(|| path);
-    log::debug(format!("Config rules path: {:?}", p));
+    Ok(cnf)
 }
 
-// Builds the rules path based on the config setting
-fn build_rules_path(str_path: String) -> PathBuf {
+// Builds a path based on a config setting, relative paths are based on the base directory
+fn build_path(str_path: String, base_dir: &Path) -> PathBuf {
     let mut path = PathBuf::new();
     let base_path = PathBuf::from(str_path);
 
     if !base_path.has_root() {
-        path.push(current_dir().unwrap());
+        path.push(base_dir);
     }
 
     path.push(base_path);
 
     path
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_path() {
+        let base = Path::new("/etc/eekwatch");
+        assert_eq!(build_path("rules".to_string(), base), PathBuf::from("/etc/eekwatch/rules"));
+        assert_eq!(build_path("/var/rules".to_string(), base), PathBuf::from("/var/rules"));
+    }
+
+    #[test]
+    fn test_search_paths() {
+        let cwd = Path::new("/home/user/app");
+
+        let paths = search_paths(cwd, Some(PathBuf::from("/xdg")), Some(PathBuf::from("/home/user")));
+        let json: Vec<&PathBuf> = paths.iter().filter(|p| p.extension().unwrap() == "json").collect();
+        assert_eq!(
+            json,
+            vec![
+                &PathBuf::from("/home/user/app/config-dev.json"),
+                &PathBuf::from("/home/user/app/config.json"),
+                &PathBuf::from("/xdg/eekwatch/config.json"),
+                &PathBuf::from("/etc/eekwatch/config.json"),
+            ]
+        );
+        assert_eq!(paths[1], PathBuf::from("/home/user/app/config-dev.toml"));
+        assert!(paths.contains(&PathBuf::from("/etc/eekwatch/config.yml")));
+
+        // Falls back to ~/.config when XDG_CONFIG_HOME isn't set
+        let paths = search_paths(cwd, None, Some(PathBuf::from("/home/user")));
+        assert!(paths.contains(&PathBuf::from("/home/user/.config/eekwatch/config.yaml")));
+
+        let paths = search_paths(cwd, None, None);
+        assert_eq!(paths.len(), 3 * format::EXTENSIONS.len());
+    }
+}
@@ -0,0 +1,576 @@
+use crate::commands::checks::{flap, handle_alerts, remediate, run_check, silence, CheckResult};
+use crate::rules::{self, get_all_rules, RuleConfig};
+use crate::state::{self, State};
+use crate::{alerts, config, duration, format, log, server};
+use chrono::{DateTime, Utc};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, PoisonError,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Interval used when a rule doesn't define an `interval` or `schedule`
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+/// Timeout used when a rule doesn't define a `timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest the scheduler sleeps before looking for rules that are due
+const TICK: Duration = Duration::from_secs(1);
+
+/// When a rule should run
+enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    fn from_rule(rule: &RuleConfig) -> Result<Schedule, String> {
+        if let Some(expr) = &rule.schedule {
+            return rules::parse_schedule(expr)
+                .map(|s| Schedule::Cron(Box::new(s)))
+                .map_err(|e| format!("Rule '{}' has an invalid schedule: {}", rule.name, e));
+        }
+
+        match &rule.interval {
+            Some(i) => match duration::parse(i) {
+                Some(d) if !d.is_zero() => Ok(Schedule::Interval(d)),
+                _ => Err(format!("Rule '{}' has an invalid interval: {}", rule.name, i)),
+            },
+            None => Ok(Schedule::Interval(DEFAULT_INTERVAL)),
+        }
+    }
+
+    /// Get the next time the rule should run after the given time
+    fn next_after(&self, time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Schedule::Interval(d) => Some(time + chrono::Duration::from_std(*d).ok()?),
+            Schedule::Cron(s) => s.after(&time).next(),
+        }
+    }
+}
+
+/// A rule loaded into the daemon
+struct ScheduledRule {
+    /// Rule file name the rule was loaded from
+    key: String,
+    rule: Arc<RuleConfig>,
+    schedule: Schedule,
+    timeout: Duration,
+    next_run: Option<DateTime<Utc>>,
+    running: Arc<AtomicBool>,
+}
+
+impl ScheduledRule {
+    fn new(key: String, rule: RuleConfig) -> Result<ScheduledRule, String> {
+        let schedule = Schedule::from_rule(&rule)?;
+        let timeout = match &rule.timeout {
+            Some(t) => match duration::parse(t) {
+                Some(d) if !d.is_zero() => d,
+                _ => return Err(format!("Rule '{}' has an invalid timeout: {}", rule.name, t)),
+            },
+            None => DEFAULT_TIMEOUT,
+        };
+
+        // Interval rules run straight away, cron rules wait for their first slot
+        let now = Utc::now();
+        let next_run = match schedule {
+            Schedule::Interval(_) => Some(now),
+            Schedule::Cron(_) => schedule.next_after(now),
+        };
+
+        Ok(ScheduledRule {
+            key,
+            rule: Arc::new(rule),
+            schedule,
+            timeout,
+            next_run,
+            running: Arc::new(AtomicBool::new(false)),
+        })
+    }
+}
+
+/// Rules that changed between two rule sets
+#[derive(Debug, Default, PartialEq)]
+struct RuleChanges {
+    added: Vec<String>,
+    changed: Vec<String>,
+    removed: Vec<String>,
+}
+
+/// Run the daemon, each rule is checked on its own schedule until the process is stopped
+///
+/// The config and rules are reloaded on SIGHUP, or when the files change if `daemon.watch` is set.
+/// On SIGTERM or SIGINT any alerts waiting for a digest are sent and the state is saved.
+pub fn run() {
+    let rules = match get_all_rules() {
+        Ok(r) => r,
+        Err(e) => {
+            log::error(e);
+            Default::default()
+        }
+    };
+
+    let mut scheduled: Vec<ScheduledRule> = Vec::new();
+    for (key, rule) in rules {
+        match ScheduledRule::new(key, rule) {
+            Ok(sr) => scheduled.push(sr),
+            Err(e) => log::error(e),
+        }
+    }
+
+    if scheduled.is_empty() {
+        log::warn("No rules loaded, the daemon has nothing to run".to_string());
+    }
+
+    log::info(format!("Daemon started with {} rule(s)", scheduled.len()));
+
+    let state = state::load();
+    alerts::rate_limit::restore(&state);
+    let state = Arc::new(Mutex::new(state));
+
+    // Not restarted on reload, a new address needs a restart
+    if let Some(addr) = &config::get().daemon.listen {
+        server::start(addr, Arc::clone(&state));
+    }
+
+    let reload = Arc::new(AtomicBool::new(false));
+    register_reload_signal(&reload);
+    let stop = Arc::new(AtomicBool::new(false));
+    register_stop_signals(&stop);
+    // Held so the watcher keeps running, it's replaced after a reload in case the paths changed
+    let mut _watcher = watch_files(&reload);
+
+    while !stop.load(Ordering::SeqCst) {
+        if reload.swap(false, Ordering::SeqCst) {
+            reload_rules(&mut scheduled);
+            _watcher = watch_files(&reload);
+        }
+
+        let now = Utc::now();
+        for sr in scheduled.iter_mut() {
+            if sr.next_run.is_some_and(|next| next <= now) {
+                sr.next_run = sr.schedule.next_after(now);
+                log::trace(format!("Starting rule '{}', next run at {:?}", sr.rule.name, sr.next_run));
+                start_check(sr, &state);
+            }
+        }
+
+        if alerts::digest::due(now) {
+            let state = Arc::clone(&state);
+            thread::spawn(move || {
+                alerts::digest::flush();
+                save_state(&state);
+            });
+        }
+
+        // Sleep until the next rule is due
+        let wait = scheduled
+            .iter()
+            .filter_map(|sr| sr.next_run)
+            .min()
+            .and_then(|next| (next - Utc::now()).to_std().ok())
+            .unwrap_or(Duration::ZERO)
+            .min(TICK);
+        thread::sleep(wait);
+    }
+
+    log::info("Daemon stopping".to_string());
+    // Alerts still waiting for the digest would otherwise be lost
+    alerts::digest::flush();
+    save_state(&state);
+}
+
+/// Save the state along with the alerts sent, called once the alerts have gone out so they're counted
+fn save_state(state: &Mutex<State>) {
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    let _lock = state::lock();
+    state::sync_commands(&mut state);
+    alerts::rate_limit::store(&mut state);
+    state::save(&state);
+}
+
+/// Flag a reload when the process gets a SIGHUP
+#[cfg(unix)]
+fn register_reload_signal(reload: &Arc<AtomicBool>) {
+    if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(reload)) {
+        log::error(format!("Failed to register SIGHUP handler: {}", e));
+    }
+}
+
+#[cfg(not(unix))]
+fn register_reload_signal(_reload: &Arc<AtomicBool>) {}
+
+/// Flag a stop when the process gets a SIGTERM or SIGINT, so waiting alerts can be sent first
+#[cfg(unix)]
+fn register_stop_signals(stop: &Arc<AtomicBool>) {
+    for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+        if let Err(e) = signal_hook::flag::register(signal, Arc::clone(stop)) {
+            log::error(format!("Failed to register handler for signal {}: {}", signal, e));
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn register_stop_signals(_stop: &Arc<AtomicBool>) {}
+
+/// Watch the config file and rules directory for changes if enabled in the config
+///
+/// The watcher stops when the returned value is dropped.
+fn watch_files(reload: &Arc<AtomicBool>) -> Option<RecommendedWatcher> {
+    let cnf = config::get();
+    if !cnf.daemon.watch {
+        return None;
+    }
+
+    let config_path = config::config_path().ok()?.clone();
+    let rules_path = cnf.rules_path();
+    let state_path = cnf.state_path();
+
+    let flag = Arc::clone(reload);
+    let handler_rules_path = rules_path.clone();
+    let handler_config_path = config_path.clone();
+    let handler = move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else {
+            return;
+        };
+
+        if !matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            return;
+        }
+
+        // Only reload for the config file and rule files, not everything that shares the directories
+        let relevant = event.paths.iter().any(|p| {
+            *p == handler_config_path
+                || (p.parent() == Some(handler_rules_path.as_path())
+                    && *p != state_path
+                    && format::is_supported(p))
+        });
+
+        if relevant {
+            flag.store(true, Ordering::SeqCst);
+        }
+    };
+
+    let mut watcher = match notify::recommended_watcher(handler) {
+        Ok(w) => w,
+        Err(e) => {
+            log::error(format!("Failed to start file watcher: {}", e));
+            return None;
+        }
+    };
+
+    // Editors often replace files rather than writing to them, so watch the directory holding the config
+    let config_dir = config_path.parent().unwrap_or(Path::new("/"));
+    for dir in [config_dir, rules_path.as_path()] {
+        if let Err(e) = watcher.watch(dir, RecursiveMode::NonRecursive) {
+            log::error(format!("Failed to watch {}: {}", dir.display(), e));
+        }
+    }
+
+    Some(watcher)
+}
+
+/// Reload the config and rules, keeping the current set if the new one isn't valid
+fn reload_rules(scheduled: &mut Vec<ScheduledRule>) {
+    log::info("Reloading config and rules".to_string());
+
+    let cnf = match config::config_path().and_then(|p| config::read_config(p)) {
+        Ok(c) => c,
+        Err(e) => {
+            log::error(format!("Reload failed, keeping the current config and rules: {}", e));
+            return;
+        }
+    };
+
+    let new_rules = match rules::load_all_rules(&cnf) {
+        Ok(r) => r,
+        Err(errors) => {
+            for e in errors {
+                log::error(e);
+            }
+            log::error("Reload failed, keeping the current config and rules".to_string());
+            return;
+        }
+    };
+
+    let changes = match reschedule(scheduled, new_rules) {
+        Ok(c) => c,
+        Err(e) => {
+            log::error(e);
+            log::error("Reload failed, keeping the current config and rules".to_string());
+            return;
+        }
+    };
+    config::set(cnf);
+
+    log::info(format!(
+        "Reload complete, {} rule(s) loaded. Added: [{}], changed: [{}], removed: [{}]",
+        scheduled.len(),
+        changes.added.join(", "),
+        changes.changed.join(", "),
+        changes.removed.join(", ")
+    ));
+}
+
+/// Swap in the added and changed rules, leaving the current set as it was if one isn't valid
+///
+/// A changed rule keeps the running flag of the version it replaces so it doesn't start while a
+/// check of the old version is still going.
+fn reschedule(
+    scheduled: &mut Vec<ScheduledRule>,
+    new_rules: HashMap<String, RuleConfig>,
+) -> Result<RuleChanges, String> {
+    let changes = diff_rules(scheduled, &new_rules);
+
+    // Build everything up front so a bad rule leaves the current set running
+    let mut new_scheduled: Vec<ScheduledRule> = Vec::new();
+    for (key, rule) in new_rules {
+        let unchanged = !changes.added.contains(&key) && !changes.changed.contains(&key);
+        if unchanged {
+            continue;
+        }
+
+        let mut sr = ScheduledRule::new(key, rule)?;
+        if let Some(old) = scheduled.iter().find(|old| old.key == sr.key) {
+            sr.running = Arc::clone(&old.running);
+        }
+        new_scheduled.push(sr);
+    }
+
+    // Unchanged rules keep their schedule
+    for sr in scheduled.drain(..) {
+        if !changes.changed.contains(&sr.key) && !changes.removed.contains(&sr.key) {
+            new_scheduled.push(sr);
+        }
+    }
+
+    *scheduled = new_scheduled;
+    Ok(changes)
+}
+
+/// Compare the running rules with a new set of rules
+fn diff_rules(current: &[ScheduledRule], new_rules: &HashMap<String, RuleConfig>) -> RuleChanges {
+    let mut changes = RuleChanges::default();
+
+    for (key, rule) in new_rules {
+        match current.iter().find(|sr| sr.key == *key) {
+            Some(sr) if *sr.rule == *rule => {}
+            Some(_) => changes.changed.push(key.clone()),
+            None => changes.added.push(key.clone()),
+        }
+    }
+
+    for sr in current {
+        if !new_rules.contains_key(&sr.key) {
+            changes.removed.push(sr.key.clone());
+        }
+    }
+
+    changes.added.sort();
+    changes.changed.sort();
+    changes.removed.sort();
+
+    changes
+}
+
+/// Start a rule check in the background
+fn start_check(sr: &ScheduledRule, state: &Arc<Mutex<State>>) {
+    if sr.running.swap(true, Ordering::SeqCst) {
+        log::warn(format!(
+            "Rule '{}' is still running from the last run, skipping",
+            sr.rule.name
+        ));
+        return;
+    }
+
+    let rule = Arc::clone(&sr.rule);
+    let running = Arc::clone(&sr.running);
+    let state = Arc::clone(state);
+    let timeout = sr.timeout;
+
+    thread::spawn(move || {
+        // A panic in one check shouldn't stop the rule from being scheduled again
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            check_with_timeout(&rule, timeout, &state)
+        }));
+        if result.is_err() {
+            log::error(format!("Rule '{}' failed unexpectedly", rule.name));
+        }
+
+        running.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Run the rule check, giving up if it and any remediation take longer than the timeout
+///
+/// A check thread can't be stopped, so one that timed out is waited for before returning. The rule
+/// stays marked as running meanwhile and later runs are skipped rather than piling up threads.
+fn check_with_timeout(rule: &Arc<RuleConfig>, timeout: Duration, state: &Mutex<State>) {
+    let deadline = Instant::now() + timeout;
+    let mut workers = Vec::new();
+
+    match check_until(rule, timeout, deadline, &mut workers) {
+        Ok(results) => {
+            alerts::logging::log_results(&rule.name, &results);
+            let mut outcome = {
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                let _lock = state::lock();
+                state::sync_commands(&mut state);
+                let mut outcome = flap::process_results(rule, &results, &mut state);
+                silence::apply(&rule.name, &mut outcome, &mut state);
+                outcome
+            };
+
+            if outcome.suppressed.is_none() {
+                remediate(rule, &mut outcome.failures, Some(deadline), || {
+                    check_until(rule, timeout, deadline, &mut workers)
+                });
+            }
+
+            handle_alerts(outcome, rule);
+            save_state(state);
+        }
+        Err(e) => log::error(e),
+    }
+
+    for worker in workers {
+        if !worker.is_finished() {
+            log::warn(format!(
+                "Rule '{}' check is still running after timing out, waiting for it to finish",
+                rule.name
+            ));
+        }
+        let _ = worker.join();
+    }
+}
+
+/// Run the rule check on its own thread, giving up at the deadline
+///
+/// The thread is added to `workers` so the caller can wait for it.
+fn check_until(
+    rule: &Arc<RuleConfig>,
+    timeout: Duration,
+    deadline: Instant,
+    workers: &mut Vec<JoinHandle<()>>,
+) -> Result<Vec<CheckResult>, String> {
+    let (tx, rx) = mpsc::channel();
+    let check_rule = Arc::clone(rule);
+    workers.push(thread::spawn(move || {
+        let _ = tx.send(run_check(&check_rule));
+    }));
+
+    match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(format!("Rule '{}' timed out after {:?}", rule.name, timeout)),
+        Err(RecvTimeoutError::Disconnected) => Err(format!("Rule '{}' check failed unexpectedly", rule.name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn rule(interval: Option<&str>, schedule: Option<&str>) -> RuleConfig {
+        let mut rule: RuleConfig = serde_json::from_str(
+            r#"{"name": "test", "rule_type": "disk", "rules": [], "alerts": {}}"#,
+        )
+        .unwrap();
+        rule.interval = interval.map(String::from);
+        rule.schedule = schedule.map(String::from);
+        rule
+    }
+
+    #[test]
+    fn test_interval_schedule() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+
+        let s = Schedule::from_rule(&rule(Some("5m"), None)).unwrap();
+        assert_eq!(s.next_after(start), Some(start + chrono::Duration::minutes(5)));
+
+        let s = Schedule::from_rule(&rule(None, None)).unwrap();
+        assert_eq!(s.next_after(start), Some(start + chrono::Duration::minutes(1)));
+
+        assert!(Schedule::from_rule(&rule(Some("soon"), None)).is_err());
+        assert!(Schedule::from_rule(&rule(Some("0s"), None)).is_err());
+    }
+
+    #[test]
+    fn test_cron_schedule() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 3, 0).unwrap();
+
+        // 5 field cron format
+        let s = Schedule::from_rule(&rule(None, Some("*/15 * * * *"))).unwrap();
+        assert_eq!(
+            s.next_after(start),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 12, 15, 0).unwrap())
+        );
+
+        // Schedule takes priority over interval
+        let s = Schedule::from_rule(&rule(Some("5m"), Some("0 0 * * * *"))).unwrap();
+        assert_eq!(
+            s.next_after(start),
+            Some(Utc.with_ymd_and_hms(2024, 1, 1, 13, 0, 0).unwrap())
+        );
+
+        assert!(Schedule::from_rule(&rule(None, Some("not cron"))).is_err());
+    }
+
+    #[test]
+    fn test_diff_rules() {
+        let current = vec![
+            ScheduledRule::new("kept".to_string(), rule(Some("5m"), None)).unwrap(),
+            ScheduledRule::new("changed".to_string(), rule(Some("5m"), None)).unwrap(),
+            ScheduledRule::new("removed".to_string(), rule(Some("5m"), None)).unwrap(),
+        ];
+
+        let new_rules = HashMap::from([
+            ("kept".to_string(), rule(Some("5m"), None)),
+            ("changed".to_string(), rule(Some("10m"), None)),
+            ("added".to_string(), rule(Some("5m"), None)),
+        ]);
+
+        assert_eq!(
+            diff_rules(&current, &new_rules),
+            RuleChanges {
+                added: vec!["added".to_string()],
+                changed: vec!["changed".to_string()],
+                removed: vec!["removed".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_reschedule_running() {
+        let mut scheduled = vec![ScheduledRule::new("changed".to_string(), rule(Some("5m"), None)).unwrap()];
+        let old_running = Arc::clone(&scheduled[0].running);
+        old_running.store(true, Ordering::SeqCst);
+
+        let new_rules = HashMap::from([("changed".to_string(), rule(Some("10m"), None))]);
+        let changes = reschedule(&mut scheduled, new_rules).unwrap();
+        assert_eq!(changes.changed, vec!["changed".to_string()]);
+        assert_eq!(scheduled[0].rule.interval.as_deref(), Some("10m"));
+
+        // The new version doesn't start until the old check finishes
+        let state = Arc::new(Mutex::new(State::default()));
+        start_check(&scheduled[0], &state);
+        assert!(scheduled[0].running.load(Ordering::SeqCst));
+        old_running.store(false, Ordering::SeqCst);
+        assert!(!scheduled[0].running.load(Ordering::SeqCst));
+
+        // A bad rule leaves the current set in place
+        let new_rules = HashMap::from([("changed".to_string(), rule(Some("soon"), None))]);
+        assert!(reschedule(&mut scheduled, new_rules).is_err());
+        assert_eq!(scheduled[0].rule.interval.as_deref(), Some("10m"));
+    }
+}
@@ -0,0 +1,52 @@
+use regex::Regex;
+use std::time::Duration;
+
+/// Parse a human duration string such as "30s", "5m", "2h" or "1d"
+///
+/// Multiple parts can be combined, "1h30m" is an hour and a half.
+pub fn parse(duration: &str) -> Option<Duration> {
+    let rx = Regex::new(r"(\d+)\s*(ms|s|m|h|d|w)").ok()?;
+    let trimmed = duration.trim();
+
+    // Make sure the whole string is made up of duration parts
+    if trimmed.is_empty() || !rx.replace_all(trimmed, "").trim().is_empty() {
+        return None;
+    }
+
+    let mut total = Duration::ZERO;
+    for cap in rx.captures_iter(trimmed) {
+        let amount = cap[1].parse::<u64>().ok()?;
+        let part = match &cap[2] {
+            "ms" => Duration::from_millis(amount),
+            "s" => Duration::from_secs(amount),
+            "m" => Duration::from_secs(amount * 60),
+            "h" => Duration::from_secs(amount * 60 * 60),
+            "d" => Duration::from_secs(amount * 60 * 60 * 24),
+            "w" => Duration::from_secs(amount * 60 * 60 * 24 * 7),
+            _ => return None,
+        };
+        total += part;
+    }
+
+    Some(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse("1d"), Some(Duration::from_secs(86400)));
+        assert_eq!(parse("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse("250ms"), Some(Duration::from_millis(250)));
+
+        // Invalid durations
+        assert!(parse("").is_none());
+        assert!(parse("5").is_none());
+        assert!(parse("five minutes").is_none());
+    }
+}
@@ -0,0 +1,284 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+use sysinfo::{ProcessRefreshKind, ProcessesToUpdate, System, MINIMUM_CPU_UPDATE_INTERVAL};
+
+use crate::alerts::{Diagnostic, Failure};
+use crate::log;
+use crate::rules::RuleConfig;
+
+/// Longest time spent adding up directory sizes for one mount point
+const SCAN_LIMIT: Duration = Duration::from_secs(10);
+
+/// Most of a log file read when looking for its last lines
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// Diagnostic details to gather when a rule alerts
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct Enrichment {
+    /// Number of the largest directories on a failing mount point to list, for disk rules
+    pub largest_dirs: Option<usize>,
+    /// Number of processes using the most CPU and memory to list
+    pub top_processes: Option<usize>,
+    /// Log file to include the last lines of
+    pub log_file: Option<String>,
+    /// Number of log file lines to include
+    #[serde(default = "default_log_lines")]
+    pub log_lines: usize,
+    /// Include the load average
+    #[serde(default)]
+    pub load_average: bool,
+}
+
+fn default_log_lines() -> usize {
+    20
+}
+
+/// Gather the diagnostics the rule asks for, anything that can't be gathered is logged and left out
+pub fn gather(rule: &RuleConfig, failures: &[Failure]) -> Vec<Diagnostic> {
+    let Some(enrich) = &rule.enrich else {
+        return Vec::new();
+    };
+    let mut diagnostics = Vec::new();
+
+    if let Some(count) = enrich.largest_dirs.filter(|_| rule.rule_type == "disk") {
+        for failure in failures {
+            let mount = Path::new(&failure.entry);
+            if !mount.is_dir() {
+                continue;
+            }
+            match largest_dirs(mount, count, SCAN_LIMIT) {
+                Ok(d) => diagnostics.push(d),
+                Err(e) => log::warn(format!("Failed to find the largest directories on {}: {}", failure.entry, e)),
+            }
+        }
+    }
+
+    if let Some(count) = enrich.top_processes {
+        diagnostics.extend(top_processes(count));
+    }
+
+    if enrich.load_average {
+        diagnostics.push(load_average());
+    }
+
+    if let Some(path) = &enrich.log_file {
+        match tail(Path::new(path), enrich.log_lines) {
+            Ok(lines) => diagnostics.push(Diagnostic {
+                title: format!("Last {} lines of {}", lines.len(), path),
+                lines,
+            }),
+            Err(e) => log::warn(format!("Failed to read log file {}: {}", path, e)),
+        }
+    }
+
+    diagnostics
+}
+
+/// A byte count rounded to MB, such as "12 MB"
+pub fn mb(bytes: u64) -> String {
+    format!("{} MB", (bytes as f64 / 1024.0 / 1024.0).round())
+}
+
+/// Directories directly under the mount point with the most data in them
+///
+/// Other file systems mounted below it aren't counted. The scan stops at the time limit, the sizes
+/// found so far are still listed.
+fn largest_dirs(mount: &Path, count: usize, limit: Duration) -> io::Result<Diagnostic> {
+    let deadline = Instant::now() + limit;
+    let device = device_id(&fs::metadata(mount)?);
+    let mut sizes: Vec<(PathBuf, u64)> = Vec::new();
+    let mut partial = false;
+
+    for entry in fs::read_dir(mount)? {
+        let entry = entry?;
+        let Ok(meta) = entry.metadata() else { continue };
+        if !meta.is_dir() || device_id(&meta) != device {
+            continue;
+        }
+
+        let (size, finished) = dir_size(&entry.path(), device, deadline);
+        sizes.push((entry.path(), size));
+        if !finished {
+            partial = true;
+            break;
+        }
+    }
+
+    sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    sizes.truncate(count);
+
+    let mut title = format!("Largest directories on {}", mount.display());
+    if partial {
+        title.push_str(&format!(" (scan stopped after {:?}, sizes are partial)", limit));
+    }
+
+    Ok(Diagnostic {
+        title,
+        lines: sizes
+            .iter()
+            .map(|(path, size)| format!("{:>10}  {}", mb(*size), path.display()))
+            .collect(),
+    })
+}
+
+/// Total size of the files under a directory, false if the deadline passed before it finished
+fn dir_size(dir: &Path, device: u64, deadline: Instant) -> (u64, bool) {
+    let mut total = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        if Instant::now() > deadline {
+            return (total, false);
+        }
+
+        // Directories that can't be read are skipped, such as ones owned by another user
+        let Ok(entries) = fs::read_dir(&dir) else { continue };
+        for entry in entries.filter_map(Result::ok) {
+            // DirEntry::metadata doesn't follow symlinks
+            let Ok(meta) = entry.metadata() else { continue };
+            if meta.is_dir() {
+                if device_id(&meta) == device {
+                    dirs.push(entry.path());
+                }
+            } else if meta.is_file() {
+                total += meta.len();
+            }
+        }
+    }
+
+    (total, true)
+}
+
+/// ID of the file system a file is on
+#[cfg(unix)]
+pub(crate) fn device_id(meta: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.dev()
+}
+
+#[cfg(not(unix))]
+pub(crate) fn device_id(_meta: &fs::Metadata) -> u64 {
+    0
+}
+
+/// The processes using the most CPU and the most memory
+fn top_processes(count: usize) -> Vec<Diagnostic> {
+    let mut sys = System::new();
+    let kind = ProcessRefreshKind::nothing().with_cpu().with_memory();
+
+    // CPU usage is measured between two refreshes
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, kind);
+    thread::sleep(MINIMUM_CPU_UPDATE_INTERVAL);
+    sys.refresh_processes_specifics(ProcessesToUpdate::All, true, kind);
+
+    // Threads are listed as processes on Linux
+    let mut processes: Vec<_> = sys.processes().values().filter(|p| p.thread_kind().is_none()).collect();
+    let line = |p: &sysinfo::Process| {
+        format!(
+            "{:>6.1}% CPU {:>10}  {} ({})",
+            p.cpu_usage(),
+            mb(p.memory()),
+            p.name().to_string_lossy(),
+            p.pid()
+        )
+    };
+
+    processes.sort_by(|a, b| b.cpu_usage().total_cmp(&a.cpu_usage()));
+    let by_cpu = processes.iter().take(count).map(|p| line(p)).collect();
+
+    processes.sort_by_key(|p| std::cmp::Reverse(p.memory()));
+    let by_memory = processes.iter().take(count).map(|p| line(p)).collect();
+
+    vec![
+        Diagnostic {
+            title: "Top processes by CPU".to_string(),
+            lines: by_cpu,
+        },
+        Diagnostic {
+            title: "Top processes by memory".to_string(),
+            lines: by_memory,
+        },
+    ]
+}
+
+fn load_average() -> Diagnostic {
+    let load = System::load_average();
+    let cpus = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+
+    Diagnostic {
+        title: "Load average".to_string(),
+        lines: vec![format!(
+            "{:.2} {:.2} {:.2} (1, 5 and 15 minutes, {} CPUs)",
+            load.one, load.five, load.fifteen, cpus
+        )],
+    }
+}
+
+/// The last lines of a file, only the end of a large file is read
+fn tail(path: &Path, lines: usize) -> io::Result<Vec<String>> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(start))?;
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let text = String::from_utf8_lossy(&data);
+
+    let mut all: Vec<&str> = text.lines().collect();
+    // The first line is likely cut off part way through
+    if start > 0 && !all.is_empty() {
+        all.remove(0);
+    }
+
+    let skip = all.len().saturating_sub(lines);
+    Ok(all[skip..].iter().map(|l| l.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+
+    #[test]
+    fn test_largest_dirs() {
+        let dir = temp_dir("enrich-dirs");
+        for (name, size) in [("small", 10), ("big", 3000), ("medium", 500)] {
+            fs::create_dir_all(dir.join(name).join("nested")).unwrap();
+            fs::write(dir.join(name).join("nested/file"), vec![0; size]).unwrap();
+        }
+
+        let diagnostic = largest_dirs(&dir, 2, SCAN_LIMIT).unwrap();
+        assert_eq!(diagnostic.title, format!("Largest directories on {}", dir.display()));
+        assert_eq!(diagnostic.lines.len(), 2);
+        assert!(diagnostic.lines[0].ends_with("big"));
+        assert!(diagnostic.lines[1].ends_with("medium"));
+
+        let (size, finished) = dir_size(&dir, device_id(&fs::metadata(&dir).unwrap()), Instant::now() + SCAN_LIMIT);
+        assert_eq!((size, finished), (3510, true));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail() {
+        let dir = temp_dir("enrich-tail");
+        let path = dir.join("app.log");
+        let text: Vec<String> = (1..=5).map(|i| format!("line {}", i)).collect();
+        fs::write(&path, text.join("\n")).unwrap();
+
+        assert_eq!(tail(&path, 2).unwrap(), vec!["line 4", "line 5"]);
+        assert_eq!(tail(&path, 10).unwrap().len(), 5);
+
+        // Only the end of a large file is read, without the cut off first line
+        let long = format!("{}\nlast", "x".repeat(TAIL_BYTES as usize));
+        fs::write(&path, long).unwrap();
+        assert_eq!(tail(&path, 10).unwrap(), vec!["last"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
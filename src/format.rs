@@ -0,0 +1,84 @@
+use serde::de::DeserializeOwned;
+use std::{fs, path::Path};
+
+/// File extensions that config and rule files can use
+pub const EXTENSIONS: &[&str] = &["json", "toml", "yaml", "yml"];
+
+/// Check if a path has one of the supported file extensions
+pub fn is_supported(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| EXTENSIONS.iter().any(|e| ext == *e))
+}
+
+/// Read a JSON, TOML or YAML file, the format is picked from the file extension
+pub fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let data = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to open {} for reading: {}", path.display(), e))?;
+
+    let ext = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+
+    parse(&data, &ext).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+/// Parse data in the format matching the file extension
+fn parse<T: DeserializeOwned>(data: &str, ext: &str) -> Result<T, String> {
+    match ext {
+        "json" => serde_json::from_str(data).map_err(|e| format!("invalid JSON: {}", e)),
+        "toml" => toml::from_str(data).map_err(|e| format!("invalid TOML: {}", e)),
+        "yaml" | "yml" => serde_yaml::from_str(data).map_err(|e| format!("invalid YAML: {}", e)),
+        _ => Err(format!(
+            "unsupported file type '{}', use one of: {}",
+            ext,
+            EXTENSIONS.join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleConfig;
+
+    #[test]
+    fn test_parse_formats() {
+        let json = r#"{"name": "disks", "rule_type": "disk", "rules": [{"disk": "/"}], "alerts": {}}"#;
+        let toml = r#"
+            # Comments are allowed
+            name = "disks"
+            rule_type = "disk"
+            alerts = {}
+
+            [[rules]]
+            disk = "/"
+        "#;
+        let yaml = "
+            # Comments are allowed
+            name: disks
+            rule_type: disk
+            rules:
+              - disk: /
+            alerts: {}
+        ";
+
+        let from_json: RuleConfig = parse(json, "json").unwrap();
+        let from_toml: RuleConfig = parse(toml, "toml").unwrap();
+        let from_yaml: RuleConfig = parse(yaml, "yaml").unwrap();
+
+        assert_eq!(from_json, from_toml);
+        assert_eq!(from_json, from_yaml);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = parse::<RuleConfig>("name = ", "toml").unwrap_err();
+        assert!(err.starts_with("invalid TOML"));
+
+        let err = parse::<RuleConfig>("{", "json").unwrap_err();
+        assert!(err.starts_with("invalid JSON"));
+
+        assert!(parse::<RuleConfig>("", "ini").is_err());
+    }
+}
@@ -1,5 +1,18 @@
 pub mod alerts;
 pub mod commands;
 pub mod config;
+pub mod daemon;
+pub mod duration;
+pub mod enrich;
+pub mod format;
 pub mod log;
+pub mod process;
+pub mod remediation;
 pub mod rules;
+pub mod secret;
+pub mod server;
+pub mod sink;
+pub mod state;
+pub mod template;
+#[cfg(test)]
+pub mod test_util;
@@ -1,40 +1,336 @@
-use crate::config;
-use std::borrow::Borrow;
+use crate::config::{self, LogOutput};
+use crate::sink::{self, Priority};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Borrow,
+    env, fmt,
+    path::Path,
+    str::FromStr,
+    sync::atomic::{AtomicU8, Ordering},
+};
 
-#[derive(Debug)]
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FORMAT: AtomicU8 = AtomicU8::new(LogFormat::Text as u8);
+
+/// Lowest level of message that is logged
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    #[serde(alias = "warning")]
+    Warn = 3,
+    Error = 4,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "trace" => Ok(Level::Trace),
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" | "warning" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!(
+                "invalid log level '{}', use one of: trace, debug, info, warn, error",
+                s
+            )),
+        }
+    }
+}
+
+/// How log lines are written to the console
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text = 0,
+    /// One JSON object per line
+    Json = 1,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format '{}', use text or json", s)),
+        }
+    }
+}
+
+/// Details a log message is about, added as fields in JSON and journald output
+#[derive(Debug, Default)]
+pub struct Fields<'a> {
+    pub rule: Option<&'a str>,
+    /// What was checked, such as a mount point
+    pub target: Option<&'a str>,
+    pub severity: Option<&'a str>,
+    /// Measured value
+    pub value: Option<&'a str>,
+}
+
+impl Fields<'_> {
+    fn pairs(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("rule", self.rule),
+            ("target", self.target),
+            ("severity", self.severity),
+            ("value", self.value),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.map(|v| (k, v)))
+        .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 enum LogTypes {
+    Trace,
+    Debug,
     Info,
     Warn,
     Error,
+    /// A failed check, logged at the warn level
     Fail,
 }
 
-pub fn debug(msg: String) {
-    if *config::DEBUG.get_or_init(|| false) {
-        println!("DEBUG: {:#?}", msg);
+impl LogTypes {
+    fn level(self) -> Level {
+        match self {
+            LogTypes::Trace => Level::Trace,
+            LogTypes::Debug => Level::Debug,
+            LogTypes::Info => Level::Info,
+            LogTypes::Warn | LogTypes::Fail => Level::Warn,
+            LogTypes::Error => Level::Error,
+        }
     }
 }
 
+/// Set the lowest level of message that is logged
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn set_format(format: LogFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// Check if messages at a level are logged
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Log level from the `EEKWATCH_LOG_LEVEL` environment variable
+pub fn env_level() -> Option<Level> {
+    env_setting("EEKWATCH_LOG_LEVEL")
+}
+
+/// Log format from the `EEKWATCH_LOG_FORMAT` environment variable
+pub fn env_format() -> Option<LogFormat> {
+    env_setting("EEKWATCH_LOG_FORMAT")
+}
+
+fn env_setting<T: FromStr<Err = String>>(name: &str) -> Option<T> {
+    let value = env::var(name).ok().filter(|v| !v.is_empty())?;
+    match value.parse() {
+        Ok(v) => Some(v),
+        Err(e) => {
+            let msg = format!("Ignoring {}: {}", name, e);
+            print_line(format_line(format(), LogTypes::Warn, &msg, &Fields::default()));
+            None
+        }
+    }
+}
+
+pub fn trace(msg: String) {
+    print_msg(LogTypes::Trace, msg, &Fields::default());
+}
+pub fn debug(msg: String) {
+    print_msg(LogTypes::Debug, msg, &Fields::default());
+}
 pub fn info<B: Borrow<String>>(msg: B) {
-    print_msg(LogTypes::Info, msg);
+    print_msg(LogTypes::Info, msg, &Fields::default());
 }
 pub fn warn<B: Borrow<String>>(msg: B) {
-    print_msg(LogTypes::Warn, msg);
+    print_msg(LogTypes::Warn, msg, &Fields::default());
 }
 pub fn error<B: Borrow<String>>(msg: B) {
-    print_msg(LogTypes::Error, msg);
+    print_msg(LogTypes::Error, msg, &Fields::default());
 }
 pub fn fail<B: Borrow<String>>(msg: B) {
-    print_msg(LogTypes::Fail, msg);
+    print_msg(LogTypes::Fail, msg, &Fields::default());
+}
+
+/// Log an info message about a check
+pub fn info_with<B: Borrow<String>>(msg: B, fields: &Fields) {
+    print_msg(LogTypes::Info, msg, fields);
+}
+
+/// Log a failed check
+pub fn fail_with<B: Borrow<String>>(msg: B, fields: &Fields) {
+    print_msg(LogTypes::Fail, msg, fields);
 }
 
-fn print_msg<B: Borrow<String>>(t: LogTypes, msg: B) {
-    let pre: String = match t {
-        LogTypes::Info => "INFO: ".to_string(),
-        LogTypes::Warn => "WARN: ".to_string(),
-        LogTypes::Error => "ERROR: ".to_string(),
-        LogTypes::Fail => "FAIL: ".to_string(),
+fn print_msg<B: Borrow<String>>(t: LogTypes, msg: B, fields: &Fields) {
+    if !enabled(t.level()) {
+        return;
+    }
+
+    let msg = msg.borrow();
+    let sent = match config::try_get() {
+        // The config isn't loaded yet for messages about loading it
+        None => Ok(false),
+        Some(cnf) => match cnf.log.output {
+            LogOutput::Console => Ok(false),
+            LogOutput::Syslog => {
+                sink::send_syslog(&cnf.log.syslog, priority(t), "log", &fields.pairs(), msg).map(|_| true)
+            }
+            LogOutput::Journald => send_journal(t, msg, fields).map(|_| true),
+        },
     };
 
-    println!("{}{}", pre, msg.borrow());
+    match sent {
+        Ok(true) => return,
+        Ok(false) => {}
+        // Fall back to the console so the message isn't lost
+        Err(e) => print_line(format_line(format(), LogTypes::Error, &e, &Fields::default())),
+    }
+
+    print_line(format_line(format(), t, msg, fields));
+}
+
+fn priority(t: LogTypes) -> Priority {
+    match t {
+        LogTypes::Trace | LogTypes::Debug => Priority::Debug,
+        LogTypes::Info => Priority::Info,
+        LogTypes::Warn | LogTypes::Fail => Priority::Warning,
+        LogTypes::Error => Priority::Error,
+    }
+}
+
+fn send_journal(t: LogTypes, msg: &str, fields: &Fields) -> Result<(), String> {
+    let priority = (priority(t) as u8).to_string();
+    let pairs = fields.pairs();
+    let names: Vec<String> = pairs.iter().map(|(k, _)| k.to_uppercase()).collect();
+
+    let mut journal = vec![
+        ("MESSAGE", msg),
+        ("PRIORITY", priority.as_str()),
+        ("SYSLOG_IDENTIFIER", "eekwatch"),
+    ];
+    journal.extend(names.iter().zip(&pairs).map(|(name, (_, v))| (name.as_str(), *v)));
+
+    sink::send_journal(Path::new(sink::JOURNALD_SOCKET), &journal)
+}
+
+fn format() -> LogFormat {
+    if FORMAT.load(Ordering::Relaxed) == LogFormat::Json as u8 {
+        LogFormat::Json
+    } else {
+        LogFormat::Text
+    }
+}
+
+/// Format a console log line
+fn format_line(format: LogFormat, t: LogTypes, msg: &str, fields: &Fields) -> String {
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+    if format == LogFormat::Json {
+        let mut line = serde_json::Map::new();
+        line.insert("timestamp".to_string(), timestamp.into());
+        line.insert("level".to_string(), t.level().to_string().into());
+        line.insert("message".to_string(), msg.into());
+        for (k, v) in fields.pairs() {
+            line.insert(k.to_string(), v.into());
+        }
+        return serde_json::Value::Object(line).to_string();
+    }
+
+    let pre = match t {
+        LogTypes::Trace => "TRACE",
+        LogTypes::Debug => "DEBUG",
+        LogTypes::Info => "INFO",
+        LogTypes::Warn => "WARN",
+        LogTypes::Error => "ERROR",
+        LogTypes::Fail => "FAIL",
+    };
+    format!("{} {}: {}", timestamp, pre, msg)
+}
+
+fn print_line(line: String) {
+    #[cfg(test)]
+    if CAPTURED.with(|c| c.borrow_mut().as_mut().map(|lines| lines.push(line.clone())).is_some()) {
+        return;
+    }
+
+    if config::overrides().log_to_stderr {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+#[cfg(test)]
+thread_local! {
+    static CAPTURED: std::cell::RefCell<Option<Vec<String>>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Run a function and return the console lines it logged on this thread instead of printing them
+#[cfg(test)]
+pub fn capture<F: FnOnce()>(f: F) -> Vec<String> {
+    CAPTURED.with(|c| *c.borrow_mut() = Some(Vec::new()));
+    f();
+    CAPTURED.with(|c| c.borrow_mut().take().unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!("WARNING".parse::<Level>(), Ok(Level::Warn));
+        assert_eq!("trace".parse::<Level>(), Ok(Level::Trace));
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Error > Level::Info);
+    }
+
+    #[test]
+    fn test_json_line() {
+        let fields = Fields {
+            rule: Some("disks"),
+            target: Some("/"),
+            severity: Some("critical"),
+            value: None,
+        };
+        let line = format_line(LogFormat::Json, LogTypes::Fail, "Disk low", &fields);
+        let line: serde_json::Value = serde_json::from_str(&line).unwrap();
+
+        assert_eq!(line["level"], "warn");
+        assert_eq!(line["message"], "Disk low");
+        assert_eq!(line["rule"], "disks");
+        assert_eq!(line["target"], "/");
+        assert!(line.get("value").is_none());
+        assert!(line["timestamp"].is_string());
+    }
 }
@@ -1,32 +1,198 @@
+use clap::{Parser, Subcommand, ValueEnum};
 use eekwatch::{
-    commands::{self, checks::exec_rule_check},
-    config::load_config,
-    log,
-    rules::{get_all_rules, get_single_rule},
+    commands::{
+        self,
+        checks::{exec_rule_check, send_test_alert, CheckResult},
+    },
+    config::{self, load_config, Overrides},
+    alerts, daemon, log,
+    rules::{self, get_all_rules, get_single_rule},
+    state,
 };
-use std::{env, process::exit};
+use serde::Serialize;
+use std::{path::PathBuf, process::exit};
+
+/// A simple alerting system for monitoring system resources
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Cli {
+    /// Path to the config file
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Directory to load rules from, overrides the config file
+    #[arg(long, global = true, value_name = "DIR")]
+    rules_dir: Option<PathBuf>,
+
+    /// Show debug messages, same as --log-level debug
+    #[arg(short, long, global = true, conflicts_with_all = ["quiet", "log_level"])]
+    verbose: bool,
+
+    /// Only show warnings, errors and failures, same as --log-level warn
+    #[arg(short, long, global = true, conflicts_with = "log_level")]
+    quiet: bool,
+
+    /// Lowest level of log message to show: trace, debug, info, warn or error
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<log::Level>,
+
+    /// Log message format: text or json
+    #[arg(long, global = true, value_name = "FORMAT")]
+    log_format: Option<log::LogFormat>,
+
+    /// Run checks without sending alerts or saving state
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Output format for command results
+    #[arg(short, long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a rule check, use "all" to run every rule
+    Check {
+        /// Rule name or "all"
+        rule: String,
+    },
+    /// Show details of a system resource
+    Get {
+        #[arg(value_enum)]
+        resource: Resource,
+    },
+    /// Check the config and rule files for problems
+    Validate,
+    /// Keep running and check each rule on its own schedule
+    Daemon,
+    /// Send a test alert through each of a rule's alert channels
+    TestAlert {
+        /// Rule name
+        rule: String,
+    },
+    /// Hold back a rule's alerts for a while, e.g. during planned work
+    Silence {
+        /// Rule name
+        rule: String,
+        /// How long to silence the rule for, e.g. "2h"
+        #[arg(long = "for", value_name = "DURATION", required_unless_present = "clear")]
+        length: Option<String>,
+        /// Why the rule is silenced, shown in the log
+        #[arg(long)]
+        reason: Option<String>,
+        /// Remove the silence so alerts are sent again
+        #[arg(long, conflicts_with_all = ["length", "reason"])]
+        clear: bool,
+    },
+    /// Stop a rule alerting until it recovers or gets more severe
+    Ack {
+        /// Rule name
+        rule: String,
+        /// Entry to acknowledge, such as a mount point, every alerting entry if not given
+        #[arg(long = "entry", value_name = "ENTRY")]
+        entries: Vec<String>,
+        /// Note about the acknowledgement, shown in the log
+        #[arg(long)]
+        comment: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Resource {
+    Disks,
+}
+
+/// Result of a rule check for the JSON output
+#[derive(Serialize)]
+struct RuleOutput {
+    rule: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    results: Option<Vec<CheckResult>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    let action = args[1].to_uppercase();
-    let rule = args[2].to_uppercase();
+    let cli = Cli::parse();
+    let json = cli.output == OutputFormat::Json;
 
-    // Load config
-    load_config();
+    config::OVERRIDES.get_or_init(|| Overrides {
+        config: cli.config,
+        rules_dir: cli.rules_dir,
+        log_level: match (cli.verbose, cli.quiet) {
+            (true, _) => Some(log::Level::Debug),
+            (_, true) => Some(log::Level::Warn),
+            _ => cli.log_level,
+        },
+        log_format: cli.log_format,
+        dry_run: cli.dry_run,
+        log_to_stderr: json,
+    });
+    config::apply_log_settings(None);
 
-    match action.as_str() {
-        "GET" => {
-            if rule == "DISKS" {
-                commands::disk::display_disks();
+    match cli.command {
+        Command::Validate => handle_validate_action(json),
+        Command::Get { resource } => match resource {
+            Resource::Disks => commands::disk::display_disks(json),
+        },
+        Command::Check { rule } => {
+            load_config();
+            handle_check_action(&rule, json);
+        }
+        Command::Daemon => {
+            load_config();
+            daemon::run();
+        }
+        Command::TestAlert { rule } => {
+            load_config();
+            match get_single_rule(rule) {
+                Ok(rc) => {
+                    send_test_alert(&rc);
+                    alerts::digest::flush();
+                }
+                Err(e) => {
+                    log::error(e);
+                    exit(1);
+                }
+            }
+        }
+        Command::Silence {
+            rule,
+            length,
+            reason,
+            clear,
+        } => {
+            load_config();
+            if let Err(e) = commands::silence::silence(&rule, length.as_deref(), reason, clear) {
+                log::error(e);
+                exit(1);
+            }
+        }
+        Command::Ack { rule, entries, comment } => {
+            load_config();
+            if let Err(e) = commands::ack::ack(&rule, &entries, comment) {
+                log::error(e);
+                exit(1);
             }
         }
-        "CHECK" => handle_check_action(&rule),
-        _ => log::error("Unknown action".to_string()),
     }
 }
 
-fn handle_check_action(rule: &str) {
-    if rule == "ALL" {
+fn handle_check_action(rule: &str, json: bool) {
+    let mut state = state::load();
+    alerts::rate_limit::restore(&state);
+    let mut output: Vec<RuleOutput> = Vec::new();
+
+    if rule.eq_ignore_ascii_case("all") {
         // Load all rules
         let rules = match get_all_rules() {
             Ok(rules_list) => rules_list,
@@ -37,17 +203,85 @@ fn handle_check_action(rule: &str) {
         };
 
         // Run each rule
-        for (rule_name, _) in rules {
-            match get_single_rule(rule_name) {
-                Ok(rc) => exec_rule_check(rc),
-                Err(e) => log::error(e),
-            }
+        for (_, rc) in rules {
+            let name = rc.name.clone();
+            output.push(rule_output(name, exec_rule_check(rc, &mut state)));
         }
     } else {
         // Run the specific rule
-        match get_single_rule(rule.to_owned()) {
-            Ok(rc) => exec_rule_check(rc),
-            Err(e) => log::error(e),
+        let result = get_single_rule(rule.to_owned()).and_then(|rc| exec_rule_check(rc, &mut state));
+        output.push(rule_output(rule.to_owned(), result));
+    }
+
+    // With digests turned on the alerts from every rule are sent together at the end
+    alerts::digest::flush();
+    alerts::rate_limit::store(&mut state);
+    let _lock = state::lock();
+    // Keep any silences and acknowledgements set while the checks ran
+    state::sync_commands(&mut state);
+    state::save(&state);
+
+    if json {
+        print_json(&output);
+    }
+
+    if output.iter().any(|o| o.error.is_some()) {
+        exit(1);
+    }
+}
+
+fn rule_output(rule: String, result: Result<Vec<CheckResult>, String>) -> RuleOutput {
+    match result {
+        Ok(results) => RuleOutput {
+            rule,
+            results: Some(results),
+            error: None,
+        },
+        Err(e) => {
+            log::error(&e);
+            RuleOutput {
+                rule,
+                results: None,
+                error: Some(e),
+            }
         }
     }
 }
+
+fn handle_validate_action(json: bool) {
+    let mut errors: Vec<String> = Vec::new();
+    let mut rule_count = 0;
+
+    match config::config_path().and_then(|p| config::read_config(p)) {
+        Ok(cnf) => match rules::load_all_rules(&cnf) {
+            Ok(rules) => rule_count = rules.len(),
+            Err(e) => errors.extend(e),
+        },
+        Err(e) => errors.push(e),
+    }
+
+    if json {
+        print_json(&serde_json::json!({
+            "valid": errors.is_empty(),
+            "rules": rule_count,
+            "errors": errors,
+        }));
+    } else if errors.is_empty() {
+        log::info(format!("Config and {} rule(s) are valid", rule_count));
+    } else {
+        for e in &errors {
+            log::error(e);
+        }
+    }
+
+    if !errors.is_empty() {
+        exit(1);
+    }
+}
+
+fn print_json<T: Serialize>(value: &T) {
+    match serde_json::to_string_pretty(value) {
+        Ok(out) => println!("{}", out),
+        Err(e) => log::error(format!("Failed to format output: {}", e)),
+    }
+}
@@ -0,0 +1,158 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::log;
+
+/// How often a running command is checked for having exited
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Result of a command that ran to the end
+#[derive(Debug)]
+pub struct Output {
+    /// Exit code, None if the command was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+/// Run a command, writing `stdin` to it and killing it if it runs longer than the timeout
+///
+/// The command is run directly, not through a shell. Output is only waited for until the timeout,
+/// since a background process started by the command can keep its stdout open after it exits.
+pub fn run(
+    program: &str,
+    args: &[String],
+    env: &[(String, String)],
+    stdin: &str,
+    timeout: Duration,
+) -> Result<Output, String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run '{}': {}", program, e))?;
+
+    // Write and read on their own threads so a full pipe can't block the command
+    let mut pipe_in = child.stdin.take();
+    let input = stdin.to_string();
+    let writer = thread::spawn(move || {
+        if let Some(pipe) = pipe_in.as_mut() {
+            // The command may exit without reading its input
+            let _ = pipe.write_all(input.as_bytes());
+        }
+    });
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let start = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if start.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("'{}' timed out after {:?}", program, timeout));
+            }
+            Ok(None) => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(format!("failed to wait for '{}': {}", program, e)),
+        }
+    };
+
+    // Not joined, the input doesn't matter once the command has exited
+    drop(writer);
+    let deadline = start + timeout;
+    Ok(Output {
+        code: status.code(),
+        stdout: stdout.collect(program, deadline),
+        stderr: stderr.collect(program, deadline),
+    })
+}
+
+/// Output being read from one of a command's pipes
+struct Pipe {
+    reader: thread::JoinHandle<()>,
+    out: Arc<Mutex<Vec<u8>>>,
+}
+
+impl Pipe {
+    /// Wait for the pipe to close, up to the deadline, and return what's been read
+    fn collect(self, program: &str, deadline: Instant) -> String {
+        while !self.reader.is_finished() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        if !self.reader.is_finished() {
+            log::debug(format!(
+                "Output of '{}' is still open after it exited, a background process may be holding it",
+                program
+            ));
+        }
+
+        let out = self.out.lock().unwrap_or_else(PoisonError::into_inner);
+        String::from_utf8_lossy(&out).into_owned()
+    }
+}
+
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> Pipe {
+    let out = Arc::new(Mutex::new(Vec::new()));
+    let buffer = Arc::clone(&out);
+    let reader = thread::spawn(move || {
+        let Some(mut pipe) = pipe else { return };
+        let mut chunk = [0; 4096];
+        while let Ok(n @ 1..) = pipe.read(&mut chunk) {
+            buffer.lock().unwrap_or_else(PoisonError::into_inner).extend_from_slice(&chunk[..n]);
+        }
+    });
+
+    Pipe { reader, out }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run() {
+        let env = vec![("NAME".to_string(), "disks".to_string())];
+        let args = vec!["-c".to_string(), "echo $NAME; cat; echo oops >&2; exit 3".to_string()];
+        let output = run("sh", &args, &env, "from stdin", Duration::from_secs(5)).unwrap();
+
+        assert_eq!(output.code, Some(3));
+        assert!(!output.success());
+        assert_eq!(output.stdout, "disks\nfrom stdin");
+        assert_eq!(output.stderr, "oops\n");
+    }
+
+    #[test]
+    fn test_timeout() {
+        let args = vec!["5".to_string()];
+        let start = Instant::now();
+        let result = run("sleep", &args, &[], "", Duration::from_millis(200));
+
+        assert!(result.unwrap_err().contains("timed out"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_background_process() {
+        // The sleep keeps stdout open after the shell exits
+        let args = vec!["-c".to_string(), "sleep 5 & echo started".to_string()];
+        let start = Instant::now();
+        let output = run("sh", &args, &[], "", Duration::from_millis(500)).unwrap();
+
+        assert!(output.success());
+        assert_eq!(output.stdout, "started\n");
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,422 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::enrich::{device_id, mb};
+use crate::{duration, log, process};
+
+/// How long a remediation command can run when it doesn't set a `timeout`
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A step run to fix a failing rule before alerting
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Remediation {
+    /// Delete files under `path` last modified longer ago than `older_than`
+    DeleteFiles {
+        path: String,
+        /// Age of the files to delete, e.g. "7d"
+        older_than: String,
+        /// Only delete files whose name matches this regex
+        pattern: Option<String>,
+    },
+    /// Empty a file, such as a log that is still held open
+    Truncate { path: String },
+    /// Restart a systemd unit
+    RestartUnit { unit: String },
+    /// Run a program, not through a shell
+    Command {
+        command: String,
+        #[serde(default)]
+        args: Vec<String>,
+        /// How long the command can run, e.g. "30s"
+        timeout: Option<String>,
+    },
+}
+
+/// What a remediation step did, or would do in a dry run
+#[derive(Debug, PartialEq)]
+pub struct StepResult {
+    pub description: String,
+    pub ok: bool,
+}
+
+impl Remediation {
+    /// Check the step settings are usable
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Remediation::DeleteFiles {
+                path,
+                older_than,
+                pattern,
+            } => {
+                check_path(path)?;
+                duration::parse(older_than).ok_or(format!("invalid older_than: {}", older_than))?;
+                if let Some(pattern) = pattern {
+                    Regex::new(pattern).map_err(|e| format!("invalid pattern: {}", e))?;
+                }
+            }
+            Remediation::Truncate { path } => check_path(path)?,
+            Remediation::RestartUnit { unit } => {
+                if unit.is_empty() || unit.starts_with('-') {
+                    return Err(format!("invalid unit '{}'", unit));
+                }
+            }
+            Remediation::Command { command, timeout, .. } => {
+                if command.is_empty() {
+                    return Err("command can't be empty".to_string());
+                }
+                if let Some(t) = timeout {
+                    duration::parse(t).ok_or(format!("invalid timeout: {}", t))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the step, a dry run only describes what it would do
+    ///
+    /// Commands are killed at the deadline if it comes before their own timeout.
+    pub fn run(&self, dry_run: bool, deadline: Option<Instant>) -> StepResult {
+        let limit = |timeout: Duration| {
+            deadline.map_or(timeout, |d| timeout.min(d.saturating_duration_since(Instant::now())))
+        };
+
+        let result = match self {
+            Remediation::DeleteFiles {
+                path,
+                older_than,
+                pattern,
+            } => delete_files(path, older_than, pattern.as_deref(), dry_run),
+            Remediation::Truncate { path } => truncate(path, dry_run),
+            Remediation::RestartUnit { unit } => {
+                let args = vec!["restart".to_string(), unit.clone()];
+                run_command("systemctl", &args, limit(DEFAULT_TIMEOUT), dry_run)
+            }
+            Remediation::Command { command, args, timeout } => {
+                let timeout = timeout.as_deref().and_then(duration::parse).unwrap_or(DEFAULT_TIMEOUT);
+                run_command(command, args, limit(timeout), dry_run)
+            }
+        };
+
+        match result {
+            Ok(description) => StepResult { description, ok: true },
+            Err(description) => StepResult { description, ok: false },
+        }
+    }
+}
+
+/// Run the steps in order, stopping at the first one that fails or when the deadline passes
+pub fn run_all(rule: &str, steps: &[Remediation], dry_run: bool, deadline: Option<Instant>) -> Vec<StepResult> {
+    let mut results = Vec::new();
+
+    for step in steps {
+        let result = if deadline.is_some_and(|d| Instant::now() >= d) {
+            StepResult {
+                description: "Out of time, the remaining steps weren't run".to_string(),
+                ok: false,
+            }
+        } else {
+            step.run(dry_run, deadline)
+        };
+        if result.ok {
+            log::info(format!("Rule '{}' remediation: {}", rule, result.description));
+        } else {
+            log::error(format!("Rule '{}' remediation failed: {}", rule, result.description));
+        }
+
+        let ok = result.ok;
+        results.push(result);
+        if !ok {
+            break;
+        }
+    }
+
+    results
+}
+
+/// Remediation paths must be absolute so they don't depend on where eekWatch is started
+fn check_path(path: &str) -> Result<(), String> {
+    if !Path::new(path).is_absolute() || path == "/" {
+        return Err(format!("path '{}' must be an absolute path below /", path));
+    }
+    Ok(())
+}
+
+fn delete_files(path: &str, older_than: &str, pattern: Option<&str>, dry_run: bool) -> Result<String, String> {
+    let age = duration::parse(older_than).ok_or(format!("invalid older_than: {}", older_than))?;
+    let pattern = pattern
+        .map(Regex::new)
+        .transpose()
+        .map_err(|e| format!("invalid pattern: {}", e))?;
+    let cutoff = SystemTime::now() - age;
+
+    let old = find_old_files(Path::new(path), cutoff, pattern.as_ref())
+        .map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let size: u64 = old.iter().map(|(_, len)| len).sum();
+
+    if dry_run {
+        return Ok(format!(
+            "Would delete {} files ({}) older than {} in {}",
+            old.len(),
+            mb(size),
+            older_than,
+            path
+        ));
+    }
+
+    let mut deleted = 0;
+    let mut freed = 0;
+    let mut errors = Vec::new();
+    for (file, len) in &old {
+        match fs::remove_file(file) {
+            Ok(_) => {
+                deleted += 1;
+                freed += len;
+            }
+            Err(e) => errors.push(format!("{}: {}", file.display(), e)),
+        }
+    }
+
+    let description = format!(
+        "Deleted {} files ({}) older than {} in {}",
+        deleted,
+        mb(freed),
+        older_than,
+        path
+    );
+    if errors.is_empty() {
+        Ok(description)
+    } else {
+        Err(format!("{}, {} could not be deleted: {}", description, errors.len(), errors.join(", ")))
+    }
+}
+
+/// Regular files modified before the cutoff, symlinks aren't followed
+///
+/// Other file systems mounted below the directory aren't searched. Entries that can't be read,
+/// such as directories owned by another user or files deleted during the search, are skipped.
+fn find_old_files(root: &Path, cutoff: SystemTime, pattern: Option<&Regex>) -> io::Result<Vec<(PathBuf, u64)>> {
+    let device = device_id(&fs::metadata(root)?);
+    let mut found = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if dir == root => return Err(e),
+            Err(e) => {
+                log::debug(format!("Skipping {}: {}", dir.display(), e));
+                continue;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            // DirEntry::metadata doesn't follow symlinks
+            let meta = match entry.metadata() {
+                Ok(meta) => meta,
+                Err(e) => {
+                    log::debug(format!("Skipping {}: {}", entry.path().display(), e));
+                    continue;
+                }
+            };
+
+            if meta.is_dir() {
+                if device_id(&meta) == device {
+                    dirs.push(entry.path());
+                }
+            } else if meta.is_file() && meta.modified().is_ok_and(|m| m < cutoff) {
+                let matches = pattern.map_or(true, |p| p.is_match(&entry.file_name().to_string_lossy()));
+                if matches {
+                    found.push((entry.path(), meta.len()));
+                }
+            }
+        }
+    }
+
+    Ok(found)
+}
+
+fn truncate(path: &str, dry_run: bool) -> Result<String, String> {
+    let meta = fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    if !meta.is_file() {
+        return Err(format!("{} isn't a file", path));
+    }
+
+    if dry_run {
+        return Ok(format!("Would truncate {} ({})", path, mb(meta.len())));
+    }
+
+    OpenOptions::new()
+        .write(true)
+        .open(path)
+        .and_then(|f| f.set_len(0))
+        .map_err(|e| format!("Failed to truncate {}: {}", path, e))?;
+
+    Ok(format!("Truncated {} ({})", path, mb(meta.len())))
+}
+
+fn run_command(command: &str, args: &[String], timeout: Duration, dry_run: bool) -> Result<String, String> {
+    let line = std::iter::once(command.to_string())
+        .chain(args.iter().cloned())
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if dry_run {
+        return Ok(format!("Would run '{}'", line));
+    }
+
+    let output = process::run(command, args, &[], "", timeout)?;
+    for out in [&output.stdout, &output.stderr] {
+        for l in out.lines().filter(|l| !l.trim().is_empty()) {
+            log::debug(format!("{}: {}", command, l));
+        }
+    }
+
+    if !output.success() {
+        let code = output.code.map_or("a signal".to_string(), |c| c.to_string());
+        let stderr = output.stderr.trim();
+        let mut msg = format!("'{}' exited with {}", line, code);
+        if !stderr.is_empty() {
+            msg = format!("{}: {}", msg, stderr);
+        }
+        return Err(msg);
+    }
+
+    Ok(format!("Ran '{}'", line))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::temp_dir;
+    use serde_json::json;
+    use std::fs::File;
+
+    fn aged(path: &Path, age: Duration) {
+        fs::write(path, "data").unwrap();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(SystemTime::now() - age)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_delete_files() {
+        let dir = temp_dir("remediation-delete");
+        fs::create_dir(dir.join("sub")).unwrap();
+        let day = Duration::from_secs(60 * 60 * 24);
+        aged(&dir.join("old.tmp"), day * 10);
+        aged(&dir.join("sub/old.tmp"), day * 10);
+        aged(&dir.join("old.keep"), day * 10);
+        aged(&dir.join("new.tmp"), Duration::ZERO);
+
+        // A directory that can't be read doesn't stop the others from being cleaned up
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::create_dir(dir.join("locked")).unwrap();
+            fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o000)).unwrap();
+        }
+
+        let step: Remediation = serde_json::from_value(json!({
+            "action": "delete_files",
+            "path": dir,
+            "older_than": "7d",
+            "pattern": r"\.tmp$",
+        }))
+        .unwrap();
+        assert!(step.validate().is_ok());
+
+        let dry = step.run(true, None);
+        assert!(dry.description.starts_with("Would delete 2 files"));
+        assert!(dir.join("old.tmp").exists());
+
+        let done = step.run(false, None);
+        assert!(done.ok);
+        assert!(done.description.starts_with("Deleted 2 files"));
+        assert!(!dir.join("old.tmp").exists());
+        assert!(!dir.join("sub/old.tmp").exists());
+        assert!(dir.join("old.keep").exists());
+        assert!(dir.join("new.tmp").exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(dir.join("locked"), fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_truncate() {
+        let dir = temp_dir("remediation-truncate");
+        let log = dir.join("app.log");
+        fs::write(&log, "lots of log lines").unwrap();
+        let step = Remediation::Truncate {
+            path: log.display().to_string(),
+        };
+
+        assert!(step.run(true, None).description.starts_with("Would truncate"));
+        assert_eq!(fs::metadata(&log).unwrap().len(), 17);
+        assert!(step.run(false, None).ok);
+        assert_eq!(fs::metadata(&log).unwrap().len(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_all_stops_on_failure() {
+        let steps: Vec<Remediation> = serde_json::from_value(json!([
+            {"action": "command", "command": "sh", "args": ["-c", "echo broken >&2; exit 1"]},
+            {"action": "restart_unit", "unit": "nginx"},
+        ]))
+        .unwrap();
+
+        let results = run_all("disks", &steps, false, None);
+        assert_eq!(results.len(), 1);
+        assert!(!results[0].ok);
+        assert_eq!(results[0].description, "'sh -c echo broken >&2; exit 1' exited with 1: broken");
+
+        let results = run_all("disks", &steps, true, None);
+        assert_eq!(results[1].description, "Would run 'systemctl restart nginx'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_all_deadline() {
+        let steps: Vec<Remediation> = serde_json::from_value(json!([
+            {"action": "command", "command": "sleep", "args": ["5"], "timeout": "1m"},
+            {"action": "restart_unit", "unit": "nginx"},
+        ]))
+        .unwrap();
+
+        let start = Instant::now();
+        let results = run_all("disks", &steps, false, Some(start + Duration::from_millis(200)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(results.len(), 1);
+        assert!(results[0].description.contains("timed out"));
+
+        let results = run_all("disks", &steps, false, Some(start));
+        assert_eq!(results[0].description, "Out of time, the remaining steps weren't run");
+    }
+
+    #[test]
+    fn test_validate() {
+        let step = Remediation::Truncate { path: "logs/app.log".to_string() };
+        assert!(step.validate().is_err());
+        let step = Remediation::DeleteFiles {
+            path: "/tmp".to_string(),
+            older_than: "a while".to_string(),
+            pattern: None,
+        };
+        assert!(step.validate().is_err());
+    }
+}
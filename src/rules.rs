@@ -1,94 +1,410 @@
-use crate::{config, log};
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, fs, path::PathBuf};
+use crate::commands::checks::RULE_TYPES;
+use crate::enrich::Enrichment;
+use crate::remediation::Remediation;
+use crate::secret::{self, Secret};
+use crate::config::{Config, ContactsConf};
+use crate::{alerts, config, duration, format, log};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct RuleConfig {
     pub name: String,
     pub description: Option<String>,
     pub rule_type: String,
     pub rules: Vec<HashMap<String, String>>,
+    #[serde(deserialize_with = "deserialize_alerts")]
     pub alerts: HashMap<String, Alert>,
+    /// How long the check has to keep failing before alerting, e.g. "5m"
+    #[serde(rename = "for")]
+    pub for_duration: Option<String>,
+    /// Number of failed checks in a row before alerting
+    pub consecutive_failures: Option<u32>,
+    /// Number of passed checks in a row before a failing check counts as recovered
+    pub consecutive_successes: Option<u32>,
+    /// How often the daemon runs the rule, e.g. "5m"
+    pub interval: Option<String>,
+    /// Cron expression for when the daemon runs the rule, used instead of `interval`
+    pub schedule: Option<String>,
+    /// How long the daemon waits for the check to finish, e.g. "30s"
+    pub timeout: Option<String>,
+    /// Steps run when the rule fails, before it is checked again and alerted
+    #[serde(default)]
+    pub remediation: Vec<Remediation>,
+    /// Diagnostics added to the alerts
+    pub enrich: Option<Enrichment>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Alert {
+    #[serde(default)]
     pub contacts: Vec<String>,
+    /// Alert sent instead when this one fails to send
+    pub fallback: Option<Box<Alert>>,
+    /// More contacts notified the longer the rule keeps alerting
+    #[serde(default)]
+    pub escalation: Vec<EscalationLevel>,
+    /// The channel type, set with `alert_type`, and the settings that type takes
+    #[serde(flatten)]
+    pub channel: Channel,
+}
+
+/// Alert channel types, a setting that doesn't belong to the type is rejected
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "alert_type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Channel {
+    Email {},
+    Webhook(WebhookAlert),
+    Slack(ChatAlert),
+    Discord(ChatAlert),
+    Teams(ChatAlert),
+    Ntfy(NtfyAlert),
+    Gotify(HttpAlert),
+    Pushover(HttpAlert),
+    Telegram(HttpAlert),
+    Matrix(HttpAlert),
+    Pagerduty(IncidentAlert),
+    Opsgenie(IncidentAlert),
+    Syslog {},
+    Journald {},
+    Exec(ExecAlert),
+}
+
+/// Settings of alerts sent by HTTP with nothing else to set
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpAlert {
+    /// Number of times a failed request is retried
+    pub retries: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookAlert {
+    pub url: String,
+    /// Extra HTTP headers sent with the alert
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Template for the request body, defaults to a JSON payload
+    pub body_template: Option<String>,
+    pub retries: Option<u32>,
+    /// Key used to sign the request body
+    pub secret: Option<Secret>,
+    /// File holding the key, used instead of `secret`
+    pub secret_file: Option<String>,
+}
+
+/// Settings of Slack, Discord and Teams alerts
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ChatAlert {
+    /// Incoming webhook URL
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    pub retries: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NtfyAlert {
+    /// Topic to publish to, defaults to `alerts.ntfy.topic`
+    pub topic: Option<String>,
+    pub retries: Option<u32>,
+}
+
+/// Settings of PagerDuty and Opsgenie alerts
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IncidentAlert {
+    /// Replaces the PagerDuty Events API endpoint or the Opsgenie API base
+    pub url: Option<String>,
+    /// PagerDuty routing key or Opsgenie API key
+    pub routing_key: Option<Secret>,
+    /// File holding the routing key, used instead of `routing_key`
+    pub routing_key_file: Option<String>,
+    pub retries: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ExecAlert {
+    /// Program to run, not through a shell
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// How long the command can run, e.g. "30s"
+    pub timeout: Option<String>,
+}
+
+/// Contacts added to an alert once the rule has been alerting for a while
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EscalationLevel {
+    /// How long the rule has to be alerting before this level is notified, e.g. "30m"
+    pub after: String,
+    pub contacts: Vec<String>,
+}
+
+impl Alert {
+    /// The channel type, as written in `alert_type`
+    pub fn alert_type(&self) -> &'static str {
+        match self.channel {
+            Channel::Email {} => "email",
+            Channel::Webhook(_) => "webhook",
+            Channel::Slack(_) => "slack",
+            Channel::Discord(_) => "discord",
+            Channel::Teams(_) => "teams",
+            Channel::Ntfy(_) => "ntfy",
+            Channel::Gotify(_) => "gotify",
+            Channel::Pushover(_) => "pushover",
+            Channel::Telegram(_) => "telegram",
+            Channel::Matrix(_) => "matrix",
+            Channel::Pagerduty(_) => "pagerduty",
+            Channel::Opsgenie(_) => "opsgenie",
+            Channel::Syslog {} => "syslog",
+            Channel::Journald {} => "journald",
+            Channel::Exec(_) => "exec",
+        }
+    }
+
+    /// Resolve the secret fields from the environment and files
+    ///
+    /// Returns true if any secret is written inline in the rule file.
+    fn resolve_secrets(&mut self, rules_dir: &Path) -> Result<bool, String> {
+        let mut inline = match &mut self.channel {
+            Channel::Webhook(hook) => secret::resolve("secret", &mut hook.secret, &hook.secret_file, rules_dir)?,
+            Channel::Pagerduty(incident) | Channel::Opsgenie(incident) => {
+                secret::resolve("routing_key", &mut incident.routing_key, &incident.routing_key_file, rules_dir)?
+            }
+            _ => false,
+        };
+
+        if let Some(fallback) = self.fallback.as_mut() {
+            inline |= fallback.resolve_secrets(rules_dir)?;
+        }
+
+        Ok(inline)
+    }
+}
+
+/// A rule's alerts by name, an alert without an `alert_type` is the type it's named after
+fn deserialize_alerts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, Alert>, D::Error> {
+    let alerts: HashMap<String, serde_json::Value> = HashMap::deserialize(deserializer)?;
+
+    alerts
+        .into_iter()
+        .map(|(name, mut value)| {
+            if let Some(fields) = value.as_object_mut() {
+                fields.entry("alert_type").or_insert_with(|| name.clone().into());
+            }
+            let alert = Alert::deserialize(value).map_err(|e| de::Error::custom(format!("alert '{}': {}", name, e)))?;
+            Ok((name, alert))
+        })
+        .collect()
+}
+
+/// How serious a failure is, set per rule entry with the `severity` key
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Critical => "critical",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "critical" => Ok(Severity::Critical),
+            _ => Err(format!("unknown severity '{}', use info, warning or critical", s)),
+        }
+    }
+}
+
+impl RuleConfig {
+    /// Check the rule settings are usable, `contacts` is the config the alert contacts refer to
+    pub fn validate(&self, contacts: &ContactsConf) -> Result<(), String> {
+        if !RULE_TYPES.contains(&self.rule_type.as_str()) {
+            return Err(format!(
+                "Rule '{}' has an unsupported rule type '{}'",
+                self.name, self.rule_type
+            ));
+        }
+
+        let durations = [
+            ("for", &self.for_duration),
+            ("interval", &self.interval),
+            ("timeout", &self.timeout),
+        ];
+        for (field, value) in durations {
+            if let Some(v) = value {
+                if duration::parse(v).is_none() {
+                    return Err(format!("Rule '{}' has an invalid {}: {}", self.name, field, v));
+                }
+            }
+        }
+
+        if let Some(schedule) = &self.schedule {
+            if let Err(e) = parse_schedule(schedule) {
+                return Err(format!("Rule '{}' has an invalid schedule: {}", self.name, e));
+            }
+        }
+
+        for entry in &self.rules {
+            if let Some(severity) = entry.get("severity") {
+                severity
+                    .parse::<Severity>()
+                    .map_err(|e| format!("Rule '{}' has an invalid severity: {}", self.name, e))?;
+            }
+        }
+
+        for (i, step) in self.remediation.iter().enumerate() {
+            step.validate()
+                .map_err(|e| format!("Rule '{}' has an invalid remediation step {}: {}", self.name, i + 1, e))?;
+        }
+
+        for (name, alert) in &self.alerts {
+            alerts::validate(alert, contacts)
+                .map_err(|e| format!("Rule '{}' has an invalid alert '{}': {}", self.name, name, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a cron expression, the standard 5 field format is allowed by defaulting the seconds field
+pub fn parse_schedule(schedule: &str) -> Result<cron::Schedule, String> {
+    let expr = if schedule.split_whitespace().count() == 5 {
+        format!("0 {}", schedule)
+    } else {
+        schedule.to_string()
+    };
+
+    cron::Schedule::from_str(&expr).map_err(|e| e.to_string())
 }
 
 /// Get a single rule's details
 pub fn get_single_rule(rule_name: String) -> Result<RuleConfig, String> {
-    let rules_path = config::RULES_PATH.get().unwrap();
+    let rules_path = config::get().rules_path();
     if !rules_path.exists() {
         return Err("Rules path doesn't exist, check the config file!".to_string());
     }
 
-    let rule_path = rules_path.join(format!("{}.json", rule_name));
-    log::debug(format!("Rule path: {}", rule_path.display()));
+    // The rule file can be in any of the supported formats
+    let candidates: Vec<PathBuf> = format::EXTENSIONS
+        .iter()
+        .map(|ext| rules_path.join(format!("{}.{}", rule_name, ext)))
+        .collect();
 
-    if !rule_path.exists() {
+    let Some(rule_path) = candidates.iter().find(|p| p.exists()) else {
         return Err(format!(
             "Rule doesn't exist, rule path: {}",
-            rule_path.display()
+            rules_path.join(format!("{}.{{{}}}", rule_name, format::EXTENSIONS.join(","))).display()
         ));
+    };
+    log::debug(format!("Rule path: {}", rule_path.display()));
+
+    get_rule_data(rule_path)
+}
+
+/// Get details for all rules, rules that fail to load are logged and skipped
+pub fn get_all_rules() -> Result<HashMap<String, RuleConfig>, String> {
+    let (rules, errors) = read_rules(&config::get().rules_path())?;
+    for e in errors {
+        log::error(e);
     }
 
-    let rule_details = get_rule_data(&rule_path).unwrap();
+    Ok(rules)
+}
+
+/// Load and validate all the rules of a config, failing if any rule has a problem
+pub fn load_all_rules(cnf: &Config) -> Result<HashMap<String, RuleConfig>, Vec<String>> {
+    let (rules, mut errors) = read_rules(&cnf.rules_path()).map_err(|e| vec![e])?;
+
+    for rule in rules.values() {
+        if let Err(e) = rule.validate(&cnf.contacts) {
+            errors.push(e);
+        }
+    }
 
-    Ok(rule_details)
+    if errors.is_empty() {
+        Ok(rules)
+    } else {
+        Err(errors)
+    }
 }
 
-/// Get details for all rules
-pub fn get_all_rules() -> Result<HashMap<String, RuleConfig>, String> {
+/// Read all the rule files in the rules path, returns the loaded rules and any errors
+fn read_rules(rules_path: &Path) -> Result<(HashMap<String, RuleConfig>, Vec<String>), String> {
     let mut rules: HashMap<String, RuleConfig> = HashMap::new();
+    let mut errors: Vec<String> = Vec::new();
 
-    match get_rules_list() {
-        Ok(file_list) => {
-            for rule_path in file_list {
-                let f_name = String::from(rule_path.file_name().unwrap().to_string_lossy())
-                    .replace(".json", "");
-                log::debug(format!(
-                    "Rules path: {}, file name: {}",
-                    rule_path.display(),
-                    f_name
-                ));
-
-                let rule_details: RuleConfig = get_rule_data(&rule_path).unwrap();
+    for rule_path in get_rules_list(rules_path)? {
+        let f_name = String::from(rule_path.file_stem().unwrap().to_string_lossy());
+        log::debug(format!(
+            "Rules path: {}, file name: {}",
+            rule_path.display(),
+            f_name
+        ));
 
+        match get_rule_data(&rule_path) {
+            Ok(rule_details) => {
                 rules.insert(f_name, rule_details);
             }
+            Err(e) => errors.push(e),
         }
-        Err(e) => log::error(e),
     }
 
-    Ok(rules)
+    Ok((rules, errors))
 }
 
 /// Load the rule file and parse the data
-fn get_rule_data(rule_path: &PathBuf) -> Result<RuleConfig, String> {
-    return match std::fs::File::open(rule_path) {
-        Ok(file) => {
-            let reader = std::io::BufReader::new(file);
-            match serde_json::from_reader(reader) {
-                Ok(rule) => Ok(rule),
-                Err(e) => {
-                    log::debug(e.to_string());
-                    return Err(format!("Failed to read rules file: {:?}", rule_path));
-                }
-            }
-        }
-        Err(_) => {
-            return Err(format!(
-                "Failed to open rules file for reading: {:?}",
-                rule_path
-            ));
-        }
-    };
+fn get_rule_data(rule_path: &Path) -> Result<RuleConfig, String> {
+    let mut rule: RuleConfig =
+        format::read_file(rule_path).map_err(|e| format!("Failed to read rules file: {}", e))?;
+
+    let rules_dir = rule_path.parent().unwrap_or(Path::new("/"));
+    let mut inline = false;
+    for alert in rule.alerts.values_mut() {
+        inline |= alert
+            .resolve_secrets(rules_dir)
+            .map_err(|e| format!("Rule file {}: {}", rule_path.display(), e))?;
+    }
+
+    if inline && secret::is_world_readable(rule_path) {
+        return Err(format!(
+            "Rule file {} is readable by everyone and has secrets in it, \
+             restrict its permissions (chmod 600) or move the secrets to environment variables or *_file settings",
+            rule_path.display()
+        ));
+    }
+
+    Ok(rule)
 }
 
 /// Get a list of the file listed in the rules path
-fn get_rules_list() -> Result<Vec<PathBuf>, String> {
-    let rules_path = config::RULES_PATH.get().unwrap();
+fn get_rules_list(rules_path: &Path) -> Result<Vec<PathBuf>, String> {
     if !rules_path.exists() {
         return Err("Rules path doesn't exist, check the config file!".to_string());
     }
@@ -99,7 +415,7 @@ fn get_rules_list() -> Result<Vec<PathBuf>, String> {
             // Map the directory entries to paths
             .map(|dir_entry| dir_entry.path())
             .filter_map(|path| {
-                if path.extension().map_or(false, |ext| ext == "json") {
+                if format::is_supported(&path) {
                     Some(path)
                 } else {
                     None
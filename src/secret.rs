@@ -0,0 +1,156 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{env, fmt, fs, path::Path};
+
+/// A config value that shouldn't end up in logs or debug output
+#[derive(Clone, Default, PartialEq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Secret {
+        Secret(value)
+    }
+
+    /// Get the actual secret value
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "\"[REDACTED]\"")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[REDACTED]")
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+/// Resolve a secret config field from its inline value or its `*_file` variant
+///
+/// `${ENV_VAR}` references in the inline value are replaced with the environment variable,
+/// relative file paths are based on `base_dir`. Returns true when the secret is written
+/// inline in the config file rather than coming from the environment or a file.
+pub fn resolve(
+    name: &str,
+    inline: &mut Option<Secret>,
+    file: &Option<String>,
+    base_dir: &Path,
+) -> Result<bool, String> {
+    match (inline.as_ref(), file) {
+        (Some(_), Some(_)) => Err(format!("Only one of {} and {}_file can be set", name, name)),
+        (Some(value), None) => {
+            let is_inline = has_inline_value(value.expose());
+            let value = interpolate(value.expose())
+                .map_err(|var| format!("Environment variable '{}' used by {} isn't set", var, name))?;
+            *inline = Some(Secret(value));
+
+            Ok(is_inline)
+        }
+        (None, Some(path)) => {
+            let path = base_dir.join(path);
+            let value = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read {}_file {}: {}", name, path.display(), e))?;
+            *inline = Some(Secret(value.trim_end_matches(['\r', '\n']).to_string()));
+
+            Ok(false)
+        }
+        (None, None) => Ok(false),
+    }
+}
+
+/// Check if a file can be read by every user on the system
+#[cfg(unix)]
+pub fn is_world_readable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o004 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_world_readable(_path: &Path) -> bool {
+    false
+}
+
+/// Replace `${ENV_VAR}` references, returns the name of the first variable that isn't set
+fn interpolate(value: &str) -> Result<String, String> {
+    let rx = env_regex();
+    let mut missing: Option<String> = None;
+
+    let result = rx.replace_all(value, |cap: &regex::Captures| match env::var(&cap[1]) {
+        Ok(v) => v,
+        Err(_) => {
+            missing.get_or_insert_with(|| cap[1].to_string());
+            String::new()
+        }
+    });
+
+    match missing {
+        Some(var) => Err(var),
+        None => Ok(result.to_string()),
+    }
+}
+
+/// Check if a value has anything other than environment variable references
+fn has_inline_value(value: &str) -> bool {
+    !env_regex().replace_all(value, "").trim().is_empty()
+}
+
+fn env_regex() -> Regex {
+    Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_redacted() {
+        let secret = Secret::new("hunter2".to_string());
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn test_resolve_env() {
+        env::set_var("EEKWATCH_TEST_SECRET", "from-env");
+        let base = Path::new("/");
+
+        let mut inline = Some(Secret::new("${EEKWATCH_TEST_SECRET}".to_string()));
+        assert!(!resolve("password", &mut inline, &None, base).unwrap());
+        assert_eq!(inline.unwrap().expose(), "from-env");
+
+        let mut inline = Some(Secret::new("plain".to_string()));
+        assert!(resolve("password", &mut inline, &None, base).unwrap());
+        assert_eq!(inline.unwrap().expose(), "plain");
+
+        let mut inline = Some(Secret::new("${EEKWATCH_TEST_MISSING}".to_string()));
+        assert!(resolve("password", &mut inline, &None, base).is_err());
+    }
+
+    #[test]
+    fn test_resolve_file() {
+        let dir = env::temp_dir();
+        let name = format!("eekwatch-secret-{}", std::process::id());
+        fs::write(dir.join(&name), "from-file\n").unwrap();
+
+        let mut inline = None;
+        assert!(!resolve("password", &mut inline, &Some(name.clone()), &dir).unwrap());
+        assert_eq!(inline.unwrap().expose(), "from-file");
+
+        // Can't set both
+        let mut inline = Some(Secret::new("plain".to_string()));
+        assert!(resolve("password", &mut inline, &Some(name.clone()), &dir).is_err());
+
+        fs::remove_file(dir.join(&name)).unwrap();
+    }
+}
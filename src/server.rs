@@ -0,0 +1,194 @@
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::alerts::logging;
+use crate::log;
+use crate::state::{self, State};
+
+/// Largest request body accepted
+const MAX_BODY: usize = 64 * 1024;
+
+/// Longest a client can take to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body of a `POST /ack` request
+#[derive(Deserialize, Debug)]
+struct AckRequest {
+    rule: String,
+    /// Entries to acknowledge, every alerting entry of the rule if empty
+    #[serde(default)]
+    entries: Vec<String>,
+    comment: Option<String>,
+}
+
+/// Start the local HTTP endpoint in the background
+///
+/// It has no authentication, so listening on anything but a loopback address is warned about.
+pub fn start(addr: &str, state: Arc<Mutex<State>>) {
+    let listener = match TcpListener::bind(addr) {
+        Ok(l) => l,
+        Err(e) => {
+            log::error(format!("Failed to listen on {}: {}", addr, e));
+            return;
+        }
+    };
+
+    if listener.local_addr().is_ok_and(|a| !a.ip().is_loopback()) {
+        log::warn(format!("Listening on {}, anyone who can reach it can acknowledge alerts", addr));
+    }
+    log::info(format!("Listening for acknowledgements on {}", addr));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => handle(stream, &state),
+                Err(e) => log::warn(format!("Failed to accept a connection: {}", e)),
+            }
+        }
+    });
+}
+
+fn handle(mut stream: TcpStream, state: &Mutex<State>) {
+    let _ = stream.set_read_timeout(Some(READ_TIMEOUT));
+
+    let (status, body) = match read_request(&stream) {
+        Ok((method, path, body)) => respond(&method, &path, &body, state),
+        Err(e) => (400, json!({"error": e})),
+    };
+
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    if let Err(e) = stream.write_all(response.as_bytes()) {
+        log::debug(format!("Failed to send a response: {}", e));
+    }
+}
+
+/// The method, path and body of a request
+fn read_request(stream: &TcpStream) -> Result<(String, String, Vec<u8>), String> {
+    // Stops a client sending headers forever
+    let mut reader = BufReader::new(stream.take(MAX_BODY as u64 * 2));
+
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(|e| e.to_string())?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err("invalid request line".to_string());
+    };
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Err("request ended before the headers did".to_string());
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| "invalid Content-Length".to_string())?;
+            }
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err("request body is too large".to_string());
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).map_err(|e| e.to_string())?;
+
+    Ok((method.to_string(), path.to_string(), body))
+}
+
+fn respond(method: &str, path: &str, body: &[u8], state: &Mutex<State>) -> (u16, Value) {
+    if path != "/ack" {
+        return (404, json!({"error": "not found"}));
+    }
+    if method != "POST" {
+        return (405, json!({"error": "use POST"}));
+    }
+
+    let request: AckRequest = match serde_json::from_slice(body) {
+        Ok(r) => r,
+        Err(e) => return (400, json!({"error": format!("invalid request: {}", e)})),
+    };
+
+    let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+    // Don't lose anything set from the command line since the last check
+    let _lock = state::lock();
+    state::sync_commands(&mut state);
+
+    match state.acknowledge(&request.rule, &request.entries, request.comment.clone(), Utc::now()) {
+        Ok(entries) => {
+            logging::log_ack(&request.rule, &entries, request.comment.as_deref());
+            log::info(format!("Acknowledged rule '{}' for {}", request.rule, entries.join(", ")));
+            state::save(&state);
+            (200, json!({"rule": request.rule, "acknowledged": entries}))
+        }
+        Err(e) => (409, json!({"error": e})),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            let body = r#"{"rule": "disks"}"#;
+            write!(
+                stream,
+                "POST /ack HTTP/1.1\r\nHost: localhost\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let (stream, _) = listener.accept().unwrap();
+        let (method, path, body) = read_request(&stream).unwrap();
+        assert_eq!((method.as_str(), path.as_str()), ("POST", "/ack"));
+        let request: AckRequest = serde_json::from_slice(&body).unwrap();
+        assert_eq!(request.rule, "disks");
+        assert!(request.entries.is_empty());
+
+        client.join().unwrap();
+    }
+
+    #[test]
+    fn test_respond_errors() {
+        let state = Mutex::new(State::default());
+
+        assert_eq!(respond("POST", "/", b"", &state).0, 404);
+        assert_eq!(respond("GET", "/ack", b"", &state).0, 405);
+        assert_eq!(respond("POST", "/ack", b"{\"entries\": []}", &state).0, 400);
+    }
+}
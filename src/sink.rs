@@ -0,0 +1,272 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::net::UdpSocket;
+use std::path::Path;
+
+/// Default socket of the local syslog daemon
+pub const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Socket journald reads native protocol messages from
+pub const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Enterprise number used in structured data IDs, 32473 is reserved for documentation
+const SD_ID: &str = "eekwatch@32473";
+
+/// Syslog message severity, also used as the journald `PRIORITY`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+    Debug = 7,
+}
+
+/// Syslog facility the messages are logged under
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Facility {
+    User,
+    #[default]
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Facility::User => 1,
+            Facility::Daemon => 3,
+            Facility::Local0 => 16,
+            Facility::Local1 => 17,
+            Facility::Local2 => 18,
+            Facility::Local3 => 19,
+            Facility::Local4 => 20,
+            Facility::Local5 => 21,
+            Facility::Local6 => 22,
+            Facility::Local7 => 23,
+        }
+    }
+}
+
+/// Where and how syslog messages are sent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SyslogConf {
+    /// A Unix socket path, such as /dev/log, or a `host:port` to send to over UDP
+    #[serde(default = "default_syslog_address")]
+    pub address: String,
+    #[serde(default)]
+    pub facility: Facility,
+    /// APP-NAME of the messages
+    #[serde(default = "default_app_name")]
+    pub app_name: String,
+}
+
+impl Default for SyslogConf {
+    fn default() -> SyslogConf {
+        SyslogConf {
+            address: default_syslog_address(),
+            facility: Facility::default(),
+            app_name: default_app_name(),
+        }
+    }
+}
+
+fn default_syslog_address() -> String {
+    SYSLOG_SOCKET.to_string()
+}
+
+fn default_app_name() -> String {
+    "eekwatch".to_string()
+}
+
+/// Build an RFC 5424 syslog message
+///
+/// `data` is added as structured data, `msg_id` says what kind of message it is, e.g. "alert".
+pub fn format_syslog(
+    conf: &SyslogConf,
+    priority: Priority,
+    msg_id: &str,
+    data: &[(&str, &str)],
+    msg: &str,
+) -> String {
+    let pri = conf.facility.code() * 8 + priority as u8;
+    let host = sysinfo::System::host_name().unwrap_or("-".to_string());
+
+    let structured = if data.is_empty() {
+        "-".to_string()
+    } else {
+        let params: Vec<String> = data
+            .iter()
+            .map(|(k, v)| format!("{}=\"{}\"", k, escape_param(v)))
+            .collect();
+        format!("[{} {}]", SD_ID, params.join(" "))
+    };
+
+    format!(
+        "<{}>1 {} {} {} {} {} {} {}",
+        pri,
+        Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, true),
+        header_field(&host),
+        header_field(&conf.app_name),
+        std::process::id(),
+        header_field(msg_id),
+        structured,
+        msg
+    )
+}
+
+/// Send a message to syslog
+pub fn send_syslog(
+    conf: &SyslogConf,
+    priority: Priority,
+    msg_id: &str,
+    data: &[(&str, &str)],
+    msg: &str,
+) -> Result<(), String> {
+    let message = format_syslog(conf, priority, msg_id, data, msg);
+
+    if conf.address.starts_with('/') {
+        send_unix(Path::new(&conf.address), message.as_bytes())
+    } else {
+        let socket = UdpSocket::bind("0.0.0.0:0").map_err(|e| format!("Failed to open UDP socket: {}", e))?;
+        socket
+            .send_to(message.as_bytes(), &conf.address)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to send to syslog at {}: {}", conf.address, e))
+    }
+}
+
+/// Encode fields with the journald native protocol
+///
+/// Values with new lines use the binary form, a little endian length before the value.
+pub fn format_journal(fields: &[(&str, &str)]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for (name, value) in fields {
+        out.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            out.push(b'\n');
+            out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            out.push(b'=');
+        }
+        out.extend_from_slice(value.as_bytes());
+        out.push(b'\n');
+    }
+
+    out
+}
+
+/// Send fields to journald, the field names must be upper case
+pub fn send_journal(socket: &Path, fields: &[(&str, &str)]) -> Result<(), String> {
+    send_unix(socket, &format_journal(fields))
+}
+
+#[cfg(unix)]
+fn send_unix(path: &Path, data: &[u8]) -> Result<(), String> {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound().map_err(|e| format!("Failed to open Unix socket: {}", e))?;
+    socket
+        .send_to(data, path)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to send to {}: {}", path.display(), e))
+}
+
+#[cfg(not(unix))]
+fn send_unix(path: &Path, _data: &[u8]) -> Result<(), String> {
+    Err(format!("Unix sockets aren't supported on this platform: {}", path.display()))
+}
+
+/// Header fields are printable ASCII without spaces, "-" when empty
+fn header_field(value: &str) -> String {
+    let field: String = value.chars().filter(|c| c.is_ascii_graphic()).take(48).collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+fn escape_param(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+    use std::{env, fs};
+
+    fn socket(name: &str) -> (UnixDatagram, std::path::PathBuf) {
+        let path = env::temp_dir().join(format!("eekwatch-{}-{}.sock", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        (UnixDatagram::bind(&path).unwrap(), path)
+    }
+
+    fn receive(socket: &UnixDatagram) -> Vec<u8> {
+        let mut buf = vec![0; 4096];
+        let len = socket.recv(&mut buf).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn test_syslog_unix() {
+        let (listener, path) = socket("syslog");
+        let conf = SyslogConf {
+            address: path.display().to_string(),
+            facility: Facility::Local0,
+            app_name: "eekwatch".to_string(),
+        };
+
+        send_syslog(&conf, Priority::Warning, "alert", &[("rule", "disks"), ("entry", "/\"x\"")], "Disk low").unwrap();
+
+        let msg = String::from_utf8(receive(&listener)).unwrap();
+        // local0 (16) * 8 + warning (4)
+        assert!(msg.starts_with("<132>1 "));
+        assert!(msg.contains(&format!(" eekwatch {} alert [eekwatch@32473 rule=\"disks\" entry=\"/\\\"x\\\"\"] Disk low", std::process::id())));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_syslog_udp() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conf = SyslogConf {
+            address: listener.local_addr().unwrap().to_string(),
+            ..SyslogConf::default()
+        };
+
+        send_syslog(&conf, Priority::Info, "log", &[], "hello").unwrap();
+
+        let mut buf = [0; 1024];
+        let len = listener.recv(&mut buf).unwrap();
+        let msg = String::from_utf8_lossy(&buf[..len]);
+        // daemon (3) * 8 + info (6)
+        assert!(msg.starts_with("<30>1 "));
+        assert!(msg.ends_with(" log - hello"));
+    }
+
+    #[test]
+    fn test_journal() {
+        let (listener, path) = socket("journal");
+
+        send_journal(&path, &[("MESSAGE", "line 1\nline 2"), ("RULE", "disks")]).unwrap();
+
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&13u64.to_le_bytes());
+        expected.extend_from_slice(b"line 1\nline 2\nRULE=disks\n");
+        assert_eq!(receive(&listener), expected);
+
+        fs::remove_file(path).unwrap();
+    }
+}
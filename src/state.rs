@@ -0,0 +1,416 @@
+use crate::rules::Severity;
+use crate::{config, log};
+use chrono::{DateTime, Utc};
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    path::Path,
+};
+
+/// Persistent state kept between runs
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    #[serde(default)]
+    pub rules: HashMap<String, RuleState>,
+    /// When each rate limited channel sent its recent alerts
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub alerts_sent: BTreeMap<String, Vec<DateTime<Utc>>>,
+    /// When the silences and acknowledgements were last read from the state file
+    #[serde(skip)]
+    pub synced: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RuleState {
+    #[serde(default)]
+    pub entries: HashMap<String, EntryState>,
+    /// Set by the `silence` command to hold back the rule's alerts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub silence: Option<Silence>,
+    /// The maintenance window the rule's alerts are being held back for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maintenance: Option<Silence>,
+}
+
+/// Alerts for a rule held back until a time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Silence {
+    pub until: DateTime<Utc>,
+    pub reason: Option<String>,
+    pub created: DateTime<Utc>,
+    /// Number of alerts held back so far
+    #[serde(default)]
+    pub suppressed: u32,
+}
+
+/// Tracks a single rule entry, such as a mount point in a disk rule
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct EntryState {
+    pub failing_since: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub consecutive_failures: u32,
+    #[serde(default)]
+    pub consecutive_successes: u32,
+    #[serde(default)]
+    pub alerting: bool,
+    /// When the entry started alerting, cleared when it recovers
+    pub alerting_since: Option<DateTime<Utc>>,
+    /// Severity of the latest failure, cleared when it recovers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<Severity>,
+    /// Set by the `ack` command to stop alerts until the entry recovers or gets worse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ack: Option<Ack>,
+}
+
+/// Someone is already looking at a failing entry
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ack {
+    pub at: DateTime<Utc>,
+    /// Severity when it was acknowledged, a higher severity alerts again
+    pub severity: Severity,
+    pub comment: Option<String>,
+}
+
+impl State {
+    /// Get the state for a rule entry, creating it if it doesn't exist
+    pub fn entry(&mut self, rule_name: &str, entry: &str) -> &mut EntryState {
+        self.rules
+            .entry(rule_name.to_string())
+            .or_default()
+            .entries
+            .entry(entry.to_string())
+            .or_default()
+    }
+
+    /// Acknowledge a rule's alerting entries, or all of them when `entries` is empty
+    ///
+    /// Returns the entries acknowledged, it's an error if one of `entries` isn't alerting. Entries
+    /// with no severity recorded, from a state file saved before severities were, can't be compared
+    /// against later failures so they're refused until they fail again.
+    pub fn acknowledge(
+        &mut self,
+        rule_name: &str,
+        entries: &[String],
+        comment: Option<String>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<String>, String> {
+        let not_alerting = || format!("Rule '{}' isn't alerting", rule_name);
+        let rule = self.rules.get_mut(rule_name).ok_or_else(not_alerting)?;
+
+        for name in entries {
+            match rule.entries.get(name) {
+                Some(e) if e.alerting && e.severity.is_none() => return Err(no_severity(rule_name, name)),
+                Some(e) if e.alerting => {}
+                _ => return Err(format!("Rule '{}' isn't alerting for '{}'", rule_name, name)),
+            }
+        }
+
+        let mut acknowledged = Vec::new();
+        let mut skipped = Vec::new();
+        for (name, entry) in rule.entries.iter_mut() {
+            if !entry.alerting || !(entries.is_empty() || entries.contains(name)) {
+                continue;
+            }
+            let Some(severity) = entry.severity else {
+                skipped.push(name.clone());
+                continue;
+            };
+            entry.ack = Some(Ack {
+                at: now,
+                severity,
+                comment: comment.clone(),
+            });
+            acknowledged.push(name.clone());
+        }
+
+        if acknowledged.is_empty() {
+            return match skipped.first() {
+                Some(name) => Err(no_severity(rule_name, name)),
+                None => Err(not_alerting()),
+            };
+        }
+        for name in skipped {
+            log::warn(no_severity(rule_name, &name));
+        }
+        acknowledged.sort();
+
+        Ok(acknowledged)
+    }
+}
+
+fn no_severity(rule: &str, entry: &str) -> String {
+    format!(
+        "Rule '{}' has no severity recorded for '{}', it can be acknowledged after its next failed check",
+        rule, entry
+    )
+}
+
+/// Copy the silences and acknowledgements set by the `silence` and `ack` commands since the state
+/// was last synced from the state file
+///
+/// Call it while holding the `lock`, before saving, so commands run in the meantime aren't lost.
+pub fn sync_commands(state: &mut State) {
+    let saved = load_from(&config::get().state_path());
+    merge_commands(state, saved, Utc::now());
+}
+
+/// Take the silences and acknowledgements from `saved` that are newer than the last sync
+///
+/// Older ones are left alone since this process may have since ended them. A silence missing from
+/// `saved` was cleared by the `silence` command.
+fn merge_commands(state: &mut State, saved: State, now: DateTime<Utc>) {
+    let is_new = |time: DateTime<Utc>| state.synced.map_or(true, |synced| time > synced);
+
+    let mut new_silences = Vec::new();
+    let mut new_acks = Vec::new();
+    for (name, rule) in &saved.rules {
+        if let Some(silence) = rule.silence.as_ref().filter(|s| is_new(s.created)) {
+            new_silences.push((name.clone(), silence.clone()));
+        }
+        for (entry, entry_state) in &rule.entries {
+            if let Some(ack) = entry_state.ack.as_ref().filter(|a| is_new(a.at)) {
+                new_acks.push((name.clone(), entry.clone(), ack.clone()));
+            }
+        }
+    }
+
+    for (name, rule) in state.rules.iter_mut() {
+        if !saved.rules.get(name).is_some_and(|r| r.silence.is_some()) {
+            rule.silence = None;
+        }
+    }
+    for (name, silence) in new_silences {
+        state.rules.entry(name).or_default().silence = Some(silence);
+    }
+    for (name, entry, ack) in new_acks {
+        state.entry(&name, &entry).ack = Some(ack);
+    }
+
+    state.synced = Some(now);
+}
+
+/// Lock on the state file, released when dropped
+pub struct StateLock {
+    _file: Option<File>,
+}
+
+/// Wait for the state file lock, held while the state file is read, changed and saved so the
+/// daemon and the `silence` and `ack` commands don't overwrite each other
+///
+/// Nothing is locked for dry runs since the state isn't saved.
+pub fn lock() -> StateLock {
+    if config::overrides().dry_run {
+        return StateLock { _file: None };
+    }
+
+    let path = config::get().state_path().with_extension("lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .and_then(|file| FileExt::lock_exclusive(&file).map(|_| file));
+
+    match file {
+        Ok(file) => StateLock { _file: Some(file) },
+        Err(e) => {
+            log::warn(format!("Failed to lock {}, carrying on without it: {}", path.display(), e));
+            StateLock { _file: None }
+        }
+    }
+}
+
+/// Load the state file, a missing or broken state file starts with an empty state
+pub fn load() -> State {
+    let mut state = load_from(&config::get().state_path());
+    state.synced = Some(Utc::now());
+    state
+}
+
+/// Save the state file, skipped for dry runs
+pub fn save(state: &State) {
+    if config::overrides().dry_run {
+        log::debug("Dry run, not saving the state file".to_string());
+        return;
+    }
+
+    if let Err(e) = save_to(&config::get().state_path(), state) {
+        log::error(e);
+    }
+}
+
+fn load_from(path: &Path) -> State {
+    if !path.exists() {
+        log::debug(format!("No state file found at {}", path.display()));
+        return State::default();
+    }
+
+    match fs::read_to_string(path) {
+        Ok(data) => match serde_json::from_str(&data) {
+            Ok(state) => state,
+            Err(e) => {
+                log::warn(format!(
+                    "Failed to parse state file {}, starting fresh: {}",
+                    path.display(),
+                    e
+                ));
+                State::default()
+            }
+        },
+        Err(e) => {
+            log::warn(format!(
+                "Failed to read state file {}, starting fresh: {}",
+                path.display(),
+                e
+            ));
+            State::default()
+        }
+    }
+}
+
+fn save_to(path: &Path, state: &State) -> Result<(), String> {
+    let data = serde_json::to_string_pretty(state)
+        .map_err(|e| format!("Failed to serialize state: {}", e))?;
+
+    // Write to a temp file first so a crash can't leave a half written state file
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("Failed to write state file {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("eekwatch-state-{}.json", std::process::id()));
+
+        let mut state = State::default();
+        let entry = state.entry("disks", "/");
+        entry.consecutive_failures = 2;
+        entry.failing_since = Some(Utc::now());
+        save_to(&path, &state).unwrap();
+
+        let mut loaded = load_from(&path);
+        let entry = loaded.entry("disks", "/");
+        assert_eq!(entry.consecutive_failures, 2);
+        assert!(entry.failing_since.is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_acknowledge() {
+        let mut state = State::default();
+        let now = Utc::now();
+        for (name, alerting) in [("/", true), ("/home", true), ("/var", false)] {
+            let entry = state.entry("disks", name);
+            entry.alerting = alerting;
+            entry.severity = Some(Severity::Critical);
+        }
+
+        let acked = state.acknowledge("disks", &[], Some("on it".to_string()), now).unwrap();
+        assert_eq!(acked, vec!["/", "/home"]);
+        assert_eq!(state.entry("disks", "/").ack.as_ref().unwrap().severity, Severity::Critical);
+        assert!(state.entry("disks", "/var").ack.is_none());
+
+        assert!(state.acknowledge("disks", &["/var".to_string()], None, now).is_err());
+        assert!(state.acknowledge("backups", &[], None, now).is_err());
+
+        // Entries from older state files have no severity to compare with
+        state.entry("logs", "/var/log").alerting = true;
+        let error = state.acknowledge("logs", &[], None, now).unwrap_err();
+        assert!(error.contains("no severity recorded"), "{}", error);
+        assert!(state.acknowledge("logs", &["/var/log".to_string()], None, now).is_err());
+        state.entry("logs", "/tmp").alerting = true;
+        state.entry("logs", "/tmp").severity = Some(Severity::Warning);
+        assert_eq!(state.acknowledge("logs", &[], None, now).unwrap(), vec!["/tmp"]);
+        assert!(state.entry("logs", "/var/log").ack.is_none());
+    }
+
+    #[test]
+    fn test_merge_commands() {
+        let synced = Utc::now();
+        let silence = |created: DateTime<Utc>, suppressed: u32| Silence {
+            until: synced + chrono::Duration::hours(1),
+            reason: None,
+            created,
+            suppressed,
+        };
+        let before = synced - chrono::Duration::minutes(5);
+        let after = synced + chrono::Duration::minutes(1);
+
+        let mut state = State {
+            synced: Some(synced),
+            ..Default::default()
+        };
+        state.rules.entry("disks".to_string()).or_default().silence = Some(silence(before, 3));
+        state.rules.entry("logs".to_string()).or_default().silence = Some(silence(before, 0));
+        state.entry("backups", "/");
+
+        // The saved file still has the silence counted here, a silence cleared since, and a new
+        // silence and acknowledgement from the commands
+        let mut saved = State::default();
+        saved.rules.entry("disks".to_string()).or_default().silence = Some(silence(before, 0));
+        saved.rules.entry("web".to_string()).or_default().silence = Some(silence(after, 0));
+        saved.entry("backups", "/").ack = Some(Ack {
+            at: after,
+            severity: Severity::Warning,
+            comment: None,
+        });
+        saved.entry("disks", "/").ack = Some(Ack {
+            at: before,
+            severity: Severity::Warning,
+            comment: None,
+        });
+
+        merge_commands(&mut state, saved, after);
+        assert_eq!(state.rules["disks"].silence.as_ref().unwrap().suppressed, 3);
+        assert!(state.rules["logs"].silence.is_none());
+        assert!(state.rules["web"].silence.is_some());
+        assert!(state.entry("backups", "/").ack.is_some());
+        // Acknowledgements from before the last sync were ended here
+        assert!(state.entry("disks", "/").ack.is_none());
+        assert_eq!(state.synced, Some(after));
+    }
+
+    #[test]
+    fn test_merge_commands_locked() {
+        let ack = |at: DateTime<Utc>| Ack {
+            at,
+            severity: Severity::Warning,
+            comment: None,
+        };
+        let first_sync = Utc::now();
+        let acked = first_sync + chrono::Duration::seconds(1);
+        let second_sync = first_sync + chrono::Duration::seconds(2);
+
+        // Holding the lock, a sync either reads the file after `ack` saved it or before `ack`
+        // took the lock and set its time, so the acknowledgement is always newer than that sync
+        let mut state = State::default();
+        merge_commands(&mut state, State::default(), first_sync);
+
+        let mut saved = State::default();
+        saved.entry("disks", "/").ack = Some(ack(acked));
+        merge_commands(&mut state, saved, second_sync);
+        assert_eq!(state.entry("disks", "/").ack.as_ref().map(|a| a.at), Some(acked));
+
+        // Without the lock the file could be read before an earlier acknowledgement was saved,
+        // after which it looks older than the sync and is skipped
+        let mut saved = State::default();
+        saved.entry("disks", "/data").ack = Some(ack(acked));
+        merge_commands(&mut state, saved, second_sync + chrono::Duration::seconds(1));
+        assert!(state.entry("disks", "/data").ack.is_none());
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let state = load_from(Path::new("/nonexistent/eekwatch-state.json"));
+        assert!(state.rules.is_empty());
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::HashMap;
+
+/// Values available to a template
+#[derive(Debug, Clone)]
+pub enum Value {
+    Text(String),
+    List(Vec<Context>),
+}
+
+pub type Context = HashMap<String, Value>;
+
+/// How values are escaped when they are rendered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escape {
+    None,
+    /// Escape HTML characters and turn new lines into `<br />`
+    Html,
+    /// Escape for use inside a JSON string
+    Json,
+}
+
+/// A simple template
+///
+/// - `{{name}}` is replaced with the value of `name`
+/// - `{{#if name}}...{{/if}}` is only shown when `name` is set and not empty
+/// - `{{#each name}}...{{/each}}` is repeated for each item in the list `name`
+#[derive(Debug)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Text(String),
+    Var(String),
+    If(String, Vec<Node>),
+    Each(String, Vec<Node>),
+}
+
+impl Template {
+    pub fn parse(src: &str) -> Result<Template, String> {
+        // Each open block keeps its kind, name and the nodes inside it
+        let mut stack: Vec<(String, String, Vec<Node>)> = vec![(String::new(), String::new(), Vec::new())];
+        let mut rest = src;
+
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                return Err("unclosed '{{' in template".to_string());
+            };
+            let tag = rest[start + 2..start + end].trim();
+            let mut text = &rest[..start];
+            let mut after = &rest[start + end + 2..];
+
+            // Block tags on a line of their own don't leave a blank line behind
+            let is_block = tag.starts_with('#') || tag.starts_with('/');
+            let line_before = &src[..src.len() - rest.len() + start];
+            let line_before = &line_before[line_before.rfind('\n').map_or(0, |i| i + 1)..];
+            if is_block && line_before.trim().is_empty() && after.starts_with('\n') {
+                text = &text[..text.len() - line_before.len()];
+                after = &after[1..];
+            }
+
+            if !text.is_empty() {
+                stack.last_mut().unwrap().2.push(Node::Text(text.to_string()));
+            }
+            rest = after;
+
+            if let Some(block) = tag.strip_prefix('#') {
+                let (kind, name) = block.split_once(' ').unwrap_or((block, ""));
+                if !matches!(kind, "if" | "each") || name.trim().is_empty() {
+                    return Err(format!("invalid template tag '{{{{{}}}}}'", tag));
+                }
+                stack.push((kind.to_string(), name.trim().to_string(), Vec::new()));
+            } else if let Some(kind) = tag.strip_prefix('/') {
+                if stack.len() < 2 || stack.last().unwrap().0 != kind {
+                    return Err(format!("unexpected '{{{{{}}}}}' in template", tag));
+                }
+                let (kind, name, nodes) = stack.pop().unwrap();
+                let node = if kind == "if" {
+                    Node::If(name, nodes)
+                } else {
+                    Node::Each(name, nodes)
+                };
+                stack.last_mut().unwrap().2.push(node);
+            } else if tag.is_empty() {
+                return Err("empty '{{}}' in template".to_string());
+            } else {
+                stack.last_mut().unwrap().2.push(Node::Var(tag.to_string()));
+            }
+        }
+
+        if !rest.is_empty() {
+            stack.last_mut().unwrap().2.push(Node::Text(rest.to_string()));
+        }
+
+        if stack.len() > 1 {
+            return Err(format!("'{{{{#{}}}}}' is never closed in template", stack.last().unwrap().0));
+        }
+
+        Ok(Template {
+            nodes: stack.pop().unwrap().2,
+        })
+    }
+
+    /// Render the template, unknown values are left empty
+    pub fn render(&self, ctx: &Context, escape: Escape) -> String {
+        let mut out = String::new();
+        render_nodes(&self.nodes, &mut vec![ctx], escape, &mut out);
+        out
+    }
+}
+
+fn render_nodes(nodes: &[Node], scopes: &mut Vec<&Context>, escape: Escape, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(t) => out.push_str(t),
+            Node::Var(name) => {
+                if let Some(Value::Text(v)) = lookup(scopes, name) {
+                    out.push_str(&escape_value(v, escape));
+                }
+            }
+            Node::If(name, inner) => {
+                let show = match lookup(scopes, name) {
+                    Some(Value::Text(v)) => !v.is_empty(),
+                    Some(Value::List(l)) => !l.is_empty(),
+                    None => false,
+                };
+                if show {
+                    render_nodes(inner, scopes, escape, out);
+                }
+            }
+            Node::Each(name, inner) => {
+                if let Some(Value::List(items)) = lookup(scopes, name) {
+                    for item in items {
+                        scopes.push(item);
+                        render_nodes(inner, scopes, escape, out);
+                        scopes.pop();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Find a value, the innermost scope wins
+fn lookup<'a>(scopes: &[&'a Context], name: &str) -> Option<&'a Value> {
+    scopes.iter().rev().find_map(|ctx| ctx.get(name))
+}
+
+fn escape_value(value: &str, escape: Escape) -> String {
+    match escape {
+        Escape::None => value.to_string(),
+        Escape::Html => value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+            .replace('\'', "&#39;")
+            .replace('\n', "<br />"),
+        Escape::Json => {
+            // Serializing a string gives the escaped value wrapped in quotes
+            let quoted = serde_json::to_string(value).unwrap_or_default();
+            quoted[1..quoted.len() - 1].to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(v: &str) -> Value {
+        Value::Text(v.to_string())
+    }
+
+    fn context() -> Context {
+        let item = |entry: &str, value: &str| {
+            Context::from([
+                ("entry".to_string(), text(entry)),
+                ("value".to_string(), text(value)),
+            ])
+        };
+
+        Context::from([
+            ("rule".to_string(), text("disks")),
+            ("empty".to_string(), text("")),
+            (
+                "failures".to_string(),
+                Value::List(vec![item("/", "10 MB"), item("/data", "")]),
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_render() {
+        let src = "Rule {{ rule }}{{missing}}:
+{{#each failures}}
+- {{entry}} ({{rule}}){{#if value}} {{value}}{{/if}}
+  {{/each}}
+{{#if empty}}hidden{{/if}}";
+        let t = Template::parse(src).unwrap();
+        assert_eq!(
+            t.render(&context(), Escape::None),
+            "Rule disks:\n- / (disks) 10 MB\n- /data (disks)\n"
+        );
+    }
+
+    #[test]
+    fn test_html_escape() {
+        let ctx = Context::from([("msg".to_string(), text("<b>a & b</b>\nnext"))]);
+        let t = Template::parse("<p>{{msg}}</p>").unwrap();
+        assert_eq!(
+            t.render(&ctx, Escape::Html),
+            "<p>&lt;b&gt;a &amp; b&lt;/b&gt;<br />next</p>"
+        );
+    }
+
+    #[test]
+    fn test_json_escape() {
+        let ctx = Context::from([("msg".to_string(), text("say \"hi\"\n\\"))]);
+        let t = Template::parse(r#"{"text": "{{msg}}"}"#).unwrap();
+        assert_eq!(t.render(&ctx, Escape::Json), r#"{"text": "say \"hi\"\n\\"}"#);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Template::parse("{{rule").is_err());
+        assert!(Template::parse("{{#each failures}}").is_err());
+        assert!(Template::parse("{{/each}}").is_err());
+        assert!(Template::parse("{{#each failures}}{{/if}}").is_err());
+        assert!(Template::parse("{{#unless x}}{{/unless}}").is_err());
+        assert!(Template::parse("{{}}").is_err());
+    }
+}
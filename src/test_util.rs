@@ -0,0 +1,12 @@
+//! Helpers shared by the tests
+
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty directory under the system temp directory, named for the test and this process
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("eekwatch-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}